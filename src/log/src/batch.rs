@@ -0,0 +1,185 @@
+use crate::{OpCode, Record, SequenceNumber};
+use memtable::Memtable;
+
+/// Puts and deletes that are logged as a single record, so after a crash either all of them
+/// are recovered or none are.
+///
+/// The operations of a batch get consecutive sequence numbers starting at
+/// [`WriteBatch::sequence`]. Writing the batch with [`crate::LogWriter::write`] numbers them
+/// from where the log left off, until then they count from 0.
+#[derive(Default)]
+pub struct WriteBatch {
+    sequence: SequenceNumber,
+    records: Vec<Record>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch {
+            sequence: 0,
+            records: vec![],
+        }
+    }
+
+    pub(crate) fn from_records(sequence: SequenceNumber, records: Vec<Record>) -> WriteBatch {
+        WriteBatch { sequence, records }
+    }
+
+    pub fn put(&mut self, key: &[u8], val: &[u8]) -> &mut WriteBatch {
+        self.records.push(Record::TypeValue(
+            OpCode::Write,
+            self.next_sequence(),
+            key.len(),
+            val.len(),
+            key.to_vec(),
+            val.to_vec(),
+        ));
+        self
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> &mut WriteBatch {
+        self.records.push(Record::TypeDelete(
+            OpCode::Delete,
+            self.next_sequence(),
+            key.len(),
+            key.to_vec(),
+        ));
+        self
+    }
+
+    pub fn put_str(&mut self, key: &str, val: &str) -> &mut WriteBatch {
+        self.put(key.as_bytes(), val.as_bytes())
+    }
+
+    pub fn delete_str(&mut self, key: &str) -> &mut WriteBatch {
+        self.delete(key.as_bytes())
+    }
+
+    fn next_sequence(&self) -> SequenceNumber {
+        self.sequence.wrapping_add(self.records.len() as u64)
+    }
+
+    /// Sequence number of the first operation.
+    pub fn sequence(&self) -> SequenceNumber {
+        self.sequence
+    }
+
+    /// Renumbers the operations starting at `sequence`.
+    pub fn set_sequence(&mut self, sequence: SequenceNumber) {
+        self.sequence = sequence;
+        for (i, record) in self.records.iter_mut().enumerate() {
+            let seq = sequence.wrapping_add(i as u64);
+            match record {
+                Record::TypeValue(_, record_seq, ..) => *record_seq = seq,
+                Record::TypeDelete(_, record_seq, ..) => *record_seq = seq,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    /// Operations in the order they were added.
+    pub fn iter(&self) -> std::slice::Iter<'_, Record> {
+        self.records.iter()
+    }
+
+    /// Applies every operation to `memtable` under its sequence number. Meant to be called once
+    /// the batch has been logged, so that the memtable never holds part of a batch the log does
+    /// not have.
+    pub fn insert_into<M: Memtable>(&self, memtable: &M) {
+        for record in self.records.iter() {
+            match record {
+                Record::TypeValue(_, seq, _, _, key, val) => memtable.put(key, val, *seq),
+                Record::TypeDelete(_, seq, _, key) => memtable.delete(key, *seq),
+            }
+        }
+    }
+}
+
+impl IntoIterator for WriteBatch {
+    type Item = Record;
+    type IntoIter = std::vec::IntoIter<Record>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.records.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MapMemtable;
+
+    #[test]
+    fn test_batch_keeps_operation_order() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"a", b"1").delete(b"b").put_str("c", "3");
+
+        assert_eq!(batch.len(), 3);
+        let keys: Vec<(&[u8], SequenceNumber)> = batch
+            .iter()
+            .map(|rec| (rec.key(), rec.sequence()))
+            .collect();
+        assert_eq!(keys, [(&b"a"[..], 0), (&b"b"[..], 1), (&b"c"[..], 2)]);
+
+        batch.clear();
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn test_set_sequence_renumbers_operations() {
+        let mut batch = WriteBatch::new();
+        batch.put_str("a", "1").delete_str("b");
+        batch.set_sequence(10);
+        batch.put_str("c", "3");
+
+        let seqs: Vec<SequenceNumber> = batch.iter().map(Record::sequence).collect();
+        assert_eq!(batch.sequence(), 10);
+        assert_eq!(seqs, [10, 11, 12]);
+    }
+
+    #[test]
+    fn test_batch_insert_into_memtable() {
+        let memtable = MapMemtable::default();
+        memtable.put(b"b", b"old", 1);
+
+        let mut batch = WriteBatch::new();
+        batch
+            .put_str("a", "1")
+            .put_str("b", "2")
+            .delete_str("b")
+            .put(&[0xFF, 0x00], &[0xC3, 0x28]);
+        batch.set_sequence(2);
+        batch.insert_into(&memtable);
+
+        assert_eq!(memtable.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(memtable.get(b"b"), None);
+        assert_eq!(memtable.get(&[0xFF, 0x00]), Some(vec![0xC3, 0x28]));
+    }
+
+    #[test]
+    fn test_newer_sequence_wins_whatever_the_apply_order() {
+        let memtable = MapMemtable::default();
+
+        let mut newer = WriteBatch::new();
+        newer.delete_str("a");
+        newer.set_sequence(5);
+        let mut older = WriteBatch::new();
+        older.put_str("a", "1");
+        older.set_sequence(4);
+
+        newer.insert_into(&memtable);
+        older.insert_into(&memtable);
+        assert_eq!(memtable.get(b"a"), None);
+    }
+}
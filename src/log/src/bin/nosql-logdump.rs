@@ -0,0 +1,284 @@
+//Prints the records of a log written by `LogWriter`, or cuts a log off at its first corrupt
+//record so that a node that refuses to recover can start again.
+
+use log::{repair, LogError, MappedLog, RecordRef};
+use std::env;
+use std::fmt::Write;
+use std::io::{self, Write as _};
+use std::process;
+
+const USAGE: &str = "\
+Usage: nosql-logdump [--json] [--prefix <key prefix>] <log>
+       nosql-logdump repair <log>
+
+Prints every record of <log> with its offset, sequence number, opcode, key and value
+lengths and whether it could be read. Exits with 1 if any record could not be.
+
+  --json      print one JSON object per line
+  --prefix    only print records whose key starts with the given prefix
+
+repair cuts <log> off at its first corrupt record, dropping everything from there on.";
+
+struct Options {
+    json: bool,
+    prefix: Vec<u8>,
+    path: String,
+}
+
+enum Command {
+    Dump(Options),
+    Repair(String),
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    if let [command, path] = args {
+        if command == "repair" {
+            return Ok(Command::Repair(path.clone()));
+        }
+    }
+
+    let mut json = false;
+    let mut prefix = vec![];
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--prefix" => match args.next() {
+                Some(value) => prefix = value.clone().into_bytes(),
+                None => return Err("--prefix needs a value".into()),
+            },
+            "-h" | "--help" => return Err(String::new()),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ if path.is_some() => return Err(format!("Unexpected argument {}", arg)),
+            _ => path = Some(arg.clone()),
+        }
+    }
+
+    match path {
+        Some(path) => Ok(Command::Dump(Options { json, prefix, path })),
+        None => Err("Missing log to read".into()),
+    }
+}
+
+// Key or value bytes as text, with anything outside printable ASCII escaped.
+fn escape(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
+
+// Key or value bytes as a JSON string. Bytes outside printable ASCII come out as \u00XX.
+fn json_string(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + 2);
+    out.push('"');
+    for &byte in bytes {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7E => out.push(byte as char),
+            _ => write!(out, "\\u{:04x}", byte).unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn format_record(record: &RecordRef, json: bool) -> String {
+    let (op, value_len) = match record.value() {
+        Some(value) => ("put", value.len().to_string()),
+        None => ("delete", "-".to_string()),
+    };
+
+    if json {
+        let value_len = record
+            .value()
+            .map_or("null".to_string(), |v| v.len().to_string());
+        format!(
+            "{{\"offset\":{},\"sequence\":{},\"op\":\"{}\",\"key_len\":{},\"value_len\":{},\"key\":{},\"status\":\"ok\"}}",
+            record.offset(),
+            record.sequence(),
+            op,
+            record.key().len(),
+            value_len,
+            json_string(record.key()),
+        )
+    } else {
+        format!(
+            "{:>10}  seq {:<8} {:<6} key_len {:<6} value_len {:<8} ok  {}",
+            record.offset(),
+            record.sequence(),
+            op,
+            record.key().len(),
+            value_len,
+            escape(record.key()),
+        )
+    }
+}
+
+fn format_error(err: &LogError, json: bool) -> String {
+    let offset = err.offset();
+    if json {
+        let offset = offset.map_or("null".to_string(), |offset| offset.to_string());
+        format!(
+            "{{\"offset\":{},\"status\":\"error\",\"error\":{}}}",
+            offset,
+            json_string(err.to_string().as_bytes())
+        )
+    } else {
+        let offset = offset.map_or("-".to_string(), |offset| offset.to_string());
+        format!("{:>10}  error: {}", offset, err)
+    }
+}
+
+fn dump(options: &Options, out: &mut impl io::Write) -> Result<bool, LogError> {
+    let log = MappedLog::open(&options.path)?;
+    let (mut records, mut errors) = (0u64, 0u64);
+
+    for record in log.records() {
+        match record {
+            Ok(record) => {
+                records += 1;
+                if record.key().starts_with(&options.prefix) {
+                    writeln!(out, "{}", format_record(&record, options.json))?;
+                }
+            }
+            Err(err) => {
+                errors += 1;
+                writeln!(out, "{}", format_error(&err, options.json))?;
+            }
+        }
+    }
+
+    if !options.json {
+        writeln!(
+            out,
+            "{} records, {} errors, {} bytes",
+            records,
+            errors,
+            log.len()
+        )?;
+    }
+    Ok(errors == 0)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match parse_args(&args) {
+        Ok(command) => command,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{}\n", message);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let mut out = io::stdout().lock();
+    let result = match command {
+        Command::Dump(options) => dump(&options, &mut out),
+        Command::Repair(path) => repair(&path).and_then(|cut| {
+            match cut {
+                Some(len) => writeln!(out, "Cut {} off at offset {}", path, len)?,
+                None => writeln!(out, "Every record of {} is intact, nothing to repair", path)?,
+            }
+            Ok(true)
+        }),
+    };
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        //Whatever reads the output, like `head`, has seen all it wants
+        Err(LogError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        match parse_args(&args(&["--json", "--prefix", "user:", "00001.log"])) {
+            Ok(Command::Dump(options)) => {
+                assert!(options.json);
+                assert_eq!(options.prefix, b"user:");
+                assert_eq!(options.path, "00001.log");
+            }
+            _ => panic!("Expected a dump"),
+        }
+        assert!(matches!(
+            parse_args(&args(&["repair", "00001.log"])),
+            Ok(Command::Repair(path)) if path == "00001.log"
+        ));
+        //A log that happens to be called repair
+        assert!(matches!(
+            parse_args(&args(&["repair"])),
+            Ok(Command::Dump(options)) if options.path == "repair"
+        ));
+
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&["--prefix"])).is_err());
+        assert!(parse_args(&args(&["--verbose", "00001.log"])).is_err());
+        assert!(parse_args(&args(&["a.log", "b.log"])).is_err());
+    }
+
+    // Takes a few lines and then goes away, like `head`.
+    struct Head(usize);
+
+    impl io::Write for Head {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.0 -= 1;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_dump_stops_at_closed_output() {
+        let path = env::temp_dir().join(format!("nosql-logdump-head-{}.log", process::id()));
+        let mut writer = log::LogWriter::new(&path);
+        for i in 0..10 {
+            writer.put_str(&format!("key-{}", i), "value").unwrap();
+        }
+        drop(writer);
+
+        let options = Options {
+            json: false,
+            prefix: vec![],
+            path: path.to_str().unwrap().to_string(),
+        };
+        let mut lines = vec![];
+        assert!(dump(&options, &mut lines).unwrap());
+        assert_eq!(String::from_utf8(lines).unwrap().lines().count(), 11);
+        assert!(matches!(
+            dump(&options, &mut Head(3)),
+            Err(LogError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe
+        ));
+    }
+
+    #[test]
+    fn test_json_strings_are_escaped() {
+        assert_eq!(json_string(b"foo"), "\"foo\"");
+        assert_eq!(json_string(b"a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(
+            json_string(&[0x00, b'\n', 0xFF]),
+            "\"\\u0000\\u000a\\u00ff\""
+        );
+        assert_eq!(escape(&[b'a', 0xFF]), "a\\xff");
+    }
+}
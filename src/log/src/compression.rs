@@ -0,0 +1,38 @@
+#[cfg(any(not(feature = "lz4"), test))]
+mod lz4;
+
+/// How `LogWriter` compresses records before they are split into fragments.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// LZ4 compress records of at least `min_size` bytes. A record that does not get any
+    /// smaller is written as it is.
+    ///
+    /// Built with the `lz4` feature this uses the `lz4_flex` crate, otherwise a simpler
+    /// encoder in this crate. Both write the same format, so either can read the other's logs.
+    Lz4 { min_size: usize },
+}
+
+#[cfg(feature = "lz4")]
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    lz4_flex::block::compress(data)
+}
+
+#[cfg(not(feature = "lz4"))]
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    lz4::compress(data)
+}
+
+/// `None` unless `data` decompresses to exactly `len` bytes.
+#[cfg(feature = "lz4")]
+pub(crate) fn decompress(data: &[u8], len: usize) -> Option<Vec<u8>> {
+    lz4_flex::block::decompress(data, len)
+        .ok()
+        .filter(|out| out.len() == len)
+}
+
+#[cfg(not(feature = "lz4"))]
+pub(crate) fn decompress(data: &[u8], len: usize) -> Option<Vec<u8>> {
+    lz4::decompress(data, len)
+}
@@ -0,0 +1,210 @@
+//LZ4 block format, used when the crate is built without the `lz4` feature. The output is
+//plain LZ4, so logs written with and without the feature can be read by either.
+//
+//A block is a series of sequences
+//| token (1) | literal length (0+) | literals | offset (2, LE) | match length (0+) |
+//where the high and low nibble of the token are the literal length and the match length
+//minus 4. A nibble of 15 is followed by extra length bytes, added up until one is not 255.
+//The last sequence stops after its literals.
+
+const MIN_MATCH: usize = 4;
+//The last match has to start at least this far from the end of the input
+const MATCH_START_LIMIT: usize = 12;
+//and the last bytes of the input are always literals
+const LAST_LITERALS: usize = 5;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn push_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn push_sequence(out: &mut Vec<u8>, literals: &[u8], offset_and_match: Option<(usize, usize)>) {
+    let literal_nibble = literals.len().min(15);
+    let match_nibble = offset_and_match.map_or(0, |(_, len)| (len - MIN_MATCH).min(15));
+    out.push((literal_nibble << 4 | match_nibble) as u8);
+    if literals.len() >= 15 {
+        push_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+
+    if let Some((offset, len)) = offset_and_match {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if len - MIN_MATCH >= 15 {
+            push_length(out, len - MIN_MATCH - 15);
+        }
+    }
+}
+
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    let mut anchor = 0;
+
+    if input.len() > MATCH_START_LIMIT {
+        //Last position each hashed 4 byte sequence was seen at
+        let mut table = vec![usize::MAX; 1 << HASH_BITS];
+        let match_start_limit = input.len() - MATCH_START_LIMIT;
+        let match_end_limit = input.len() - LAST_LITERALS;
+
+        let mut pos = 0;
+        while pos < match_start_limit {
+            let sequence = read_u32(input, pos);
+            let slot = &mut table[hash(sequence)];
+            let candidate = *slot;
+            *slot = pos;
+
+            if candidate == usize::MAX
+                || pos - candidate > MAX_OFFSET
+                || read_u32(input, candidate) != sequence
+            {
+                pos += 1;
+                continue;
+            }
+
+            let mut len = MIN_MATCH;
+            while pos + len < match_end_limit && input[candidate + len] == input[pos + len] {
+                len += 1;
+            }
+            push_sequence(&mut out, &input[anchor..pos], Some((pos - candidate, len)));
+            pos += len;
+            anchor = pos;
+        }
+    }
+
+    push_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+// Returns `None` unless `input` is a well formed block that expands to exactly `len` bytes.
+pub(crate) fn decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(len);
+    let mut pos = 0;
+
+    let read_length = |pos: &mut usize, mut len: usize| -> Option<usize> {
+        loop {
+            let byte = *input.get(*pos)?;
+            *pos += 1;
+            len = len.checked_add(byte as usize)?;
+            if byte != 255 {
+                return Some(len);
+            }
+        }
+    };
+
+    loop {
+        let token = *input.get(pos)?;
+        pos += 1;
+
+        let mut literals = (token >> 4) as usize;
+        if literals == 15 {
+            literals = read_length(&mut pos, literals)?;
+        }
+        let end = pos.checked_add(literals)?;
+        if end > input.len() || out.len() + literals > len {
+            return None;
+        }
+        out.extend_from_slice(&input[pos..end]);
+        pos = end;
+
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([*input.get(pos)?, *input.get(pos + 1)?]) as usize;
+        pos += 2;
+        let mut match_len = (token & 0x0F) as usize;
+        if match_len == 15 {
+            match_len = read_length(&mut pos, match_len)?;
+        }
+        match_len += MIN_MATCH;
+
+        if offset == 0 || offset > out.len() || out.len() + match_len > len {
+            return None;
+        }
+        //Matches may overlap the bytes they produce, so copy one byte at a time
+        let start = out.len() - offset;
+        for i in 0..match_len {
+            out.push(out[start + i]);
+        }
+    }
+
+    if out.len() == len {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let compressed = compress(input);
+        assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+        compressed
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"abcdefghijklm");
+        round_trip(&[7; 1000]);
+        round_trip(
+            "{\"name\": \"foo\", \"tags\": [\"a\", \"b\"]}"
+                .repeat(50)
+                .as_bytes(),
+        );
+
+        let noise: Vec<u8> = (0..5000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        round_trip(&noise);
+    }
+
+    #[test]
+    fn test_repetitive_input_shrinks() {
+        let input = "{\"id\": 1, \"status\": \"active\"}".repeat(100);
+        assert!(round_trip(input.as_bytes()).len() < input.len() / 10);
+    }
+
+    #[test]
+    fn test_malformed_blocks_are_rejected() {
+        let compressed = compress(&[7; 1000]);
+
+        assert!(decompress(&compressed, 999).is_none());
+        assert!(decompress(&compressed, 1001).is_none());
+        assert!(decompress(&compressed[..compressed.len() - 1], 1000).is_none());
+        //Match reaching back before the start of the output
+        assert!(decompress(&[0x00, 0x01, 0x00], 4).is_none());
+        assert!(decompress(&[], 0).is_none());
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_compatible_with_lz4_flex() {
+        let input = "{\"name\": \"foo\", \"tags\": [\"a\", \"b\"]}".repeat(50);
+        let input = input.as_bytes();
+
+        let ours = compress(input);
+        assert_eq!(
+            lz4_flex::block::decompress(&ours, input.len()).unwrap(),
+            input
+        );
+        let theirs = lz4_flex::block::compress(input);
+        assert_eq!(decompress(&theirs, input.len()).unwrap(), input);
+    }
+}
@@ -0,0 +1,401 @@
+use crate::compression::Compression;
+use crate::format;
+use crate::{LogWriter, SequenceNumber, SyncMode, WriteBatch};
+use std::collections::BTreeMap;
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A [`LogWriter`] that any number of threads can write to at the same time.
+///
+/// Writers encode, and compress, their batches on their own thread and push them onto a
+/// lock-free queue. A single leader thread owns the log, takes whatever has queued up, writes
+/// it in one go and syncs once for all of it as the [`SyncMode`] of the log asks. Every write
+/// gets a [`WriteHandle`] that completes once its record is as durable as the mode makes it.
+///
+/// ```no_run
+/// # use log::{ConcurrentLogWriter, LogWriter, SyncMode};
+/// let log = LogWriter::with_sync_mode("00001.log", SyncMode::EveryWrite);
+/// let writer = ConcurrentLogWriter::new(log);
+/// std::thread::scope(|s| {
+///     for i in 0..4 {
+///         let writer = &writer;
+///         s.spawn(move || {
+///             let key = format!("key-{}", i);
+///             writer.put(key.as_bytes(), b"val").wait().unwrap();
+///         });
+///     }
+/// });
+/// ```
+pub struct ConcurrentLogWriter {
+    shared: Arc<Shared>,
+    compression: Compression,
+    //First sequence number of the next batch
+    next_sequence: AtomicU64,
+    leader: Option<JoinHandle<LogWriter>>,
+}
+
+impl ConcurrentLogWriter {
+    /// Takes over `writer`, keeping its sync mode, compression and sequence numbers.
+    pub fn new(writer: LogWriter) -> ConcurrentLogWriter {
+        let shared = Arc::new(Shared {
+            queue: Queue::new(),
+            stop: AtomicBool::new(false),
+        });
+        let compression = writer.compression();
+        let next_sequence = AtomicU64::new(writer.last_sequence() + 1);
+
+        let leader_shared = Arc::clone(&shared);
+        let leader = thread::Builder::new()
+            .name("log-writer".into())
+            .spawn(move || lead(writer, &leader_shared))
+            .expect("Failed to spawn log writer thread");
+
+        ConcurrentLogWriter {
+            shared,
+            compression,
+            next_sequence,
+            leader: Some(leader),
+        }
+    }
+
+    pub fn put(&self, key: &[u8], val: &[u8]) -> WriteHandle {
+        let mut batch = WriteBatch::new();
+        batch.put(key, val);
+        self.write(&mut batch)
+    }
+
+    pub fn delete(&self, key: &[u8]) -> WriteHandle {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(&mut batch)
+    }
+
+    /// Queues every operation in `batch` as one record. The operations are given the next
+    /// consecutive sequence numbers right away, which are stored in `batch` so that it can be
+    /// applied to a memtable once the returned handle completes.
+    ///
+    /// Records land in the log in sequence number order, whichever thread queued them first.
+    pub fn write(&self, batch: &mut WriteBatch) -> WriteHandle {
+        let completion = Arc::new(Completion::default());
+        if batch.is_empty() {
+            completion.complete(Ok(()));
+            return WriteHandle {
+                completion,
+                sequence: 0,
+            };
+        }
+
+        let sequence = self
+            .next_sequence
+            .fetch_add(batch.len() as u64, Ordering::Relaxed);
+        batch.set_sequence(sequence);
+        let record = format::compress_record(format::encode_batch(batch), self.compression);
+
+        self.shared.queue.push(Entry {
+            sequence,
+            count: batch.len() as u64,
+            record,
+            completion: Arc::clone(&completion),
+        });
+        self.leader.as_ref().unwrap().thread().unpark();
+
+        WriteHandle {
+            completion,
+            sequence,
+        }
+    }
+
+    /// Sequence number of the last operation handed out, whether or not it has been written.
+    pub fn last_sequence(&self) -> SequenceNumber {
+        self.next_sequence.load(Ordering::Relaxed) - 1
+    }
+
+    /// Waits for everything queued to be written and hands the log back.
+    pub fn into_inner(mut self) -> LogWriter {
+        self.stop().unwrap()
+    }
+
+    fn stop(&mut self) -> Option<LogWriter> {
+        let leader = self.leader.take()?;
+        self.shared.stop.store(true, Ordering::Release);
+        leader.thread().unpark();
+        Some(leader.join().expect("Log writer thread panicked"))
+    }
+}
+
+impl Drop for ConcurrentLogWriter {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Completes once the write it was returned for is in the log and synced as far as the
+/// [`SyncMode`] of the log goes.
+#[must_use]
+pub struct WriteHandle {
+    completion: Arc<Completion>,
+    sequence: SequenceNumber,
+}
+
+impl WriteHandle {
+    /// Sequence number of the first operation of the write, 0 for an empty batch.
+    pub fn sequence(&self) -> SequenceNumber {
+        self.sequence
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completion.result.lock().unwrap().is_some()
+    }
+
+    pub fn wait(self) -> io::Result<()> {
+        let mut result = self.completion.result.lock().unwrap();
+        loop {
+            match result.take() {
+                Some(result) => return result,
+                None => result = self.completion.done.wait(result).unwrap(),
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Completion {
+    result: Mutex<Option<io::Result<()>>>,
+    done: Condvar,
+}
+
+impl Completion {
+    fn complete(&self, result: io::Result<()>) {
+        *self.result.lock().unwrap() = Some(result);
+        self.done.notify_all();
+    }
+}
+
+struct Shared {
+    queue: Queue,
+    //Set once no more writes can be queued, the leader exits once it has written everything
+    stop: AtomicBool,
+}
+
+struct Entry {
+    sequence: SequenceNumber,
+    count: u64,
+    record: Vec<u8>,
+    completion: Arc<Completion>,
+}
+
+struct QueueNode {
+    entry: Entry,
+    next: *mut QueueNode,
+}
+
+// Lock-free stack that any thread pushes onto and only the leader takes from. The leader
+// always takes everything at once, so a node is never popped while someone else looks at it
+// and there is no ABA problem to worry about. The order entries come out in does not matter,
+// they are put back in sequence order before being written.
+struct Queue {
+    head: AtomicPtr<QueueNode>,
+}
+
+impl Queue {
+    fn new() -> Queue {
+        Queue {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn push(&self, entry: Entry) {
+        let node = Box::into_raw(Box::new(QueueNode {
+            entry,
+            next: ptr::null_mut(),
+        }));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            //Nobody else can see the node until the exchange succeeds
+            unsafe { (*node).next = head };
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    fn take_all(&self) -> Vec<Entry> {
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut entries = vec![];
+        while !node.is_null() {
+            //Every node was made by `push` and is no longer reachable from `head`
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next;
+            entries.push(boxed.entry);
+        }
+        entries
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        self.take_all();
+    }
+}
+
+// Body of the leader thread. Writes entries strictly in sequence order: an entry whose
+// predecessor has been given its sequence number but not queued yet waits for it.
+fn lead(mut writer: LogWriter, shared: &Shared) -> LogWriter {
+    let mut pending: BTreeMap<SequenceNumber, Entry> = BTreeMap::new();
+    let mut next = writer.last_sequence() + 1;
+    //Once a write fails nothing after it can be trusted to land in the right place
+    let mut failed: Option<(io::ErrorKind, String)> = None;
+
+    loop {
+        //Read before draining, so that everything queued before the stop is seen
+        let stopping = shared.stop.load(Ordering::Acquire);
+        for entry in shared.queue.take_all() {
+            pending.insert(entry.sequence, entry);
+        }
+
+        let mut ready = vec![];
+        while let Some(entry) = pending.first_entry() {
+            //Every writer is done when stopping, a gap left then is never going to be filled
+            if *entry.key() != next && !stopping {
+                break;
+            }
+            let entry = entry.remove();
+            next = entry.sequence + entry.count;
+            ready.push(entry);
+        }
+
+        if ready.is_empty() {
+            if stopping {
+                return writer;
+            }
+            thread::park();
+            continue;
+        }
+
+        if failed.is_none() {
+            if let Err(err) = write_entries(&mut writer, &ready) {
+                failed = Some((err.kind(), err.to_string()));
+            }
+        }
+
+        for entry in ready {
+            entry.completion.complete(match &failed {
+                Some((kind, message)) => Err(io::Error::new(*kind, message.clone())),
+                None => Ok(()),
+            });
+        }
+    }
+}
+
+fn write_entries(writer: &mut LogWriter, entries: &[Entry]) -> io::Result<()> {
+    let records: Vec<&[u8]> = entries.iter().map(|entry| &entry.record[..]).collect();
+    writer.add_records(&records)?;
+
+    let last = entries.last().unwrap();
+    writer.set_last_sequence(last.sequence + last.count - 1);
+    if writer.sync_mode() == SyncMode::GroupCommit {
+        writer.sync_point().wait()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::log_path;
+    use crate::{LogReader, Record};
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+
+    fn sequences(path: &PathBuf) -> Vec<SequenceNumber> {
+        LogReader::new(path)
+            .map(Result::unwrap)
+            .map(|rec: Record| rec.sequence())
+            .collect()
+    }
+
+    #[test]
+    fn test_is_send_and_sync() {
+        fn shareable<T: Send + Sync>() {}
+        shareable::<ConcurrentLogWriter>();
+    }
+
+    #[test]
+    fn test_concurrent_writes_land_in_sequence_order() {
+        let path = log_path("concurrent_order");
+        let writer =
+            ConcurrentLogWriter::new(LogWriter::with_sync_mode(&path, SyncMode::GroupCommit));
+
+        let keys: Mutex<HashSet<(Vec<u8>, SequenceNumber)>> = Mutex::default();
+        thread::scope(|s| {
+            for t in 0..8 {
+                let (writer, keys) = (&writer, &keys);
+                s.spawn(move || {
+                    for i in 0..50 {
+                        let key = format!("key-{}-{}", t, i).into_bytes();
+                        let handle = writer.put(&key, b"val");
+                        let sequence = handle.sequence();
+                        handle.wait().unwrap();
+                        keys.lock().unwrap().insert((key, sequence));
+                    }
+                });
+            }
+        });
+        assert_eq!(writer.last_sequence(), 400);
+        let writer = writer.into_inner();
+        assert_eq!(writer.last_sequence(), 400);
+
+        assert_eq!(sequences(&path), (1..=400).collect::<Vec<_>>());
+        let written: HashSet<(Vec<u8>, SequenceNumber)> = LogReader::new(&path)
+            .map(Result::unwrap)
+            .map(|rec| (rec.key().to_vec(), rec.sequence()))
+            .collect();
+        assert_eq!(written, keys.into_inner().unwrap());
+    }
+
+    #[test]
+    fn test_batches_keep_their_sequence_numbers() {
+        let path = log_path("concurrent_batches");
+        let mut log = LogWriter::new(&path);
+        log.put(b"a", b"1").unwrap();
+        let writer = ConcurrentLogWriter::new(log);
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"b", b"2");
+        batch.delete(b"a");
+        let handle = writer.write(&mut batch);
+        assert_eq!(handle.sequence(), 2);
+        assert_eq!(batch.sequence(), 2);
+        handle.wait().unwrap();
+
+        let empty = writer.write(&mut WriteBatch::new());
+        assert!(empty.is_complete());
+        empty.wait().unwrap();
+        writer.delete(b"b").wait().unwrap();
+
+        let mut log = writer.into_inner();
+        log.put(b"c", b"3").unwrap();
+        assert_eq!(sequences(&path), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_drop_writes_everything_queued() {
+        let path = log_path("concurrent_drop");
+        let writer = ConcurrentLogWriter::new(LogWriter::new(&path));
+        let handles: Vec<WriteHandle> = (0..100).map(|i| writer.put(&[i], b"val")).collect();
+        drop(writer);
+
+        for handle in handles {
+            assert!(handle.is_complete());
+            handle.wait().unwrap();
+        }
+        assert_eq!(sequences(&path).len(), 100);
+    }
+}
@@ -0,0 +1,59 @@
+// CRC-32C (Castagnoli) as used by iSCSI, ext4 and LevelDB's log format.
+// Table driven, one byte at a time; the log is IO bound so this is more than enough.
+
+const POLYNOMIAL: u32 = 0x82F6_3B78; // reversed 0x1EDC6F41
+
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ POLYNOMIAL;
+            } else {
+                crc >>= 1;
+            }
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Continues a checksum computed over previous bytes with `data`.
+pub fn extend(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc = TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Checksum of `data`.
+pub fn checksum(data: &[u8]) -> u32 {
+    extend(0, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_vectors() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"123456789"), 0xE306_9283);
+        assert_eq!(checksum(&[0; 32]), 0x8A91_36AA);
+        assert_eq!(checksum(&[0xFF; 32]), 0x62A8_AB43);
+    }
+
+    #[test]
+    fn test_extend_matches_whole_checksum() {
+        let data = b"hello world";
+        assert_eq!(extend(checksum(&data[..5]), &data[5..]), checksum(data));
+    }
+}
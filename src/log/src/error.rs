@@ -0,0 +1,113 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while reading records back from a log.
+///
+/// Every variant that is about a particular record carries the byte offset at which that
+/// record starts so that recovery can report it or truncate the log there.
+#[derive(Debug)]
+pub enum LogError {
+    /// The log ended in the middle of a record, usually a torn write.
+    UnexpectedEof { offset: u64 },
+    /// The record was written by a format version this reader does not understand.
+    UnsupportedVersion { offset: u64, version: u8 },
+    /// The opcode is neither a write nor a delete.
+    BadOpCode { offset: u64, opcode: u8 },
+    /// The stored checksum does not match the fragment contents.
+    ChecksumMismatch { offset: u64 },
+    /// A length does not fit the block or the record it belongs to.
+    BadRecordLength { offset: u64 },
+    /// The fragment type is not one this reader knows about.
+    BadFragmentType { offset: u64, fragment_type: u8 },
+    /// A fragmented record is missing its first or last fragment.
+    FragmentOutOfOrder { offset: u64 },
+    /// A compressed record does not decompress to the length it was stored with.
+    BadCompression { offset: u64 },
+    /// The underlying file could not be read.
+    Io(io::Error),
+}
+
+impl LogError {
+    /// Offset of the record the error is about, if any.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            LogError::UnexpectedEof { offset }
+            | LogError::UnsupportedVersion { offset, .. }
+            | LogError::BadOpCode { offset, .. }
+            | LogError::ChecksumMismatch { offset }
+            | LogError::BadRecordLength { offset }
+            | LogError::BadFragmentType { offset, .. }
+            | LogError::FragmentOutOfOrder { offset }
+            | LogError::BadCompression { offset } => Some(*offset),
+            LogError::Io(_) => None,
+        }
+    }
+
+    /// Whether the reader can keep going after this error. Corruption only ever costs the rest
+    /// of a block, but nothing follows the end of the log and IO errors are not retried.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, LogError::UnexpectedEof { .. } | LogError::Io(_))
+    }
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogError::UnexpectedEof { offset } => {
+                write!(f, "Log ends in the middle of record at offset {}", offset)
+            }
+            LogError::UnsupportedVersion { offset, version } => write!(
+                f,
+                "Unsupported format version {} for record at offset {}",
+                version, offset
+            ),
+            LogError::BadOpCode { offset, opcode } => {
+                write!(
+                    f,
+                    "Unknown opcode {} for record at offset {}",
+                    opcode, offset
+                )
+            }
+            LogError::ChecksumMismatch { offset } => {
+                write!(f, "Checksum mismatch for record at offset {}", offset)
+            }
+            LogError::BadRecordLength { offset } => {
+                write!(f, "Bad record length for record at offset {}", offset)
+            }
+            LogError::BadFragmentType {
+                offset,
+                fragment_type,
+            } => write!(
+                f,
+                "Unknown fragment type {} at offset {}",
+                fragment_type, offset
+            ),
+            LogError::FragmentOutOfOrder { offset } => write!(
+                f,
+                "Fragmented record at offset {} is missing fragments",
+                offset
+            ),
+            LogError::BadCompression { offset } => write!(
+                f,
+                "Compressed record at offset {} does not decompress",
+                offset
+            ),
+            LogError::Io(err) => write!(f, "Failed to read log: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LogError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LogError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LogError {
+    fn from(err: io::Error) -> Self {
+        LogError::Io(err)
+    }
+}
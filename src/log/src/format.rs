@@ -0,0 +1,465 @@
+//The log is a sequence of fixed size blocks. Records are split into fragments so that none of
+//them crosses a block boundary and every fragment is framed as
+//| checksum (4) | length (2) | fragment type (1) | data |
+//The checksum is a CRC32C over the fragment type and the data. When less than a header's worth
+//of space is left in a block it is filled with zeroes and the next fragment starts a new block.
+//Since every block starts on a fragment boundary a reader that runs into a corrupt fragment
+//can always pick up again at the next block.
+//
+//Once the fragments are put back together a record is a write batch
+//| version (1) | sequence (8) | count (4) | entry | entry | ...
+//where sequence is the sequence number of the first entry and every entry is
+//| opcode (1) | key length (varint) | value length (varint) | key | value |
+//with value length and value only present for writes. Keys and values are arbitrary bytes.
+//Lengths are LEB128 varints, seven bits to a byte with the lowest bits first and the high
+//bit set on every byte but the last.
+//
+//Version 1 records have the same layout with every length a big-endian u64 instead. They
+//are still read but no longer written.
+//
+//A compressed record has the high bit of its version byte set and is followed by
+//| uncompressed length (varint) | LZ4 block |
+//where the block holds everything after the version byte.
+
+use crate::compression::{self, Compression};
+use crate::{LogError, OpCode, Record, RecordRef, SequenceNumber, WriteBatch};
+use std::borrow::Cow;
+use std::mem;
+
+pub const BLOCK_SIZE: usize = 32 * 1024;
+
+pub const HEADER_SIZE: usize = mem::size_of::<u32>() + mem::size_of::<u16>() + 1;
+
+/// Version of the records written by `LogWriter`.
+pub const FORMAT_VERSION: u8 = 2;
+
+//Records with fixed size lengths
+const FIXED_LENGTH_VERSION: u8 = 1;
+
+//Set in the version byte of compressed records
+const COMPRESSED_FLAG: u8 = 0x80;
+
+//An LZ4 block never expands its input by more than this factor
+const MAX_COMPRESSION_RATIO: usize = 255;
+
+type KeyType = u64;
+
+const KEY_SIZE: usize = mem::size_of::<KeyType>();
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FragmentType {
+    //Preallocated or zeroed space, never written by `LogWriter`
+    Zero = 0,
+    Full = 1,
+    First = 2,
+    Middle = 3,
+    Last = 4,
+}
+
+impl FragmentType {
+    pub fn from_u8(value: u8) -> Option<FragmentType> {
+        match value {
+            0 => Some(FragmentType::Zero),
+            1 => Some(FragmentType::Full),
+            2 => Some(FragmentType::First),
+            3 => Some(FragmentType::Middle),
+            4 => Some(FragmentType::Last),
+            _ => None,
+        }
+    }
+}
+
+const SEQUENCE_SIZE: usize = mem::size_of::<u64>();
+
+const COUNT_SIZE: usize = mem::size_of::<u32>();
+
+const BATCH_HEADER_SIZE: usize = 1 + SEQUENCE_SIZE + COUNT_SIZE;
+
+pub fn encode_batch(batch: &WriteBatch) -> Vec<u8> {
+    let mut vec: Vec<u8> = Vec::with_capacity(BATCH_HEADER_SIZE);
+
+    vec.push(FORMAT_VERSION);
+    vec.extend_from_slice(&batch.sequence().to_be_bytes());
+    vec.extend_from_slice(&(batch.len() as u32).to_be_bytes());
+
+    for record in batch.iter() {
+        match record {
+            Record::TypeValue(_, _, _, _, key, val) => {
+                vec.push(OpCode::Write as u8);
+                put_varint(&mut vec, key.len() as u64);
+                put_varint(&mut vec, val.len() as u64);
+                vec.extend_from_slice(key);
+                vec.extend_from_slice(val);
+            }
+            Record::TypeDelete(_, _, _, key) => {
+                vec.push(OpCode::Delete as u8);
+                put_varint(&mut vec, key.len() as u64);
+                vec.extend_from_slice(key);
+            }
+        }
+    }
+
+    vec
+}
+
+/// Compresses an encoded record as `compression` asks for, leaving it as it is if it is too
+/// small or would not get any smaller.
+pub fn compress_record(record: Vec<u8>, compression: Compression) -> Vec<u8> {
+    let min_size = match compression {
+        Compression::None => return record,
+        Compression::Lz4 { min_size } => min_size,
+    };
+    if record.len() < min_size.max(1) {
+        return record;
+    }
+
+    let payload = &record[1..];
+    let mut compressed = Vec::with_capacity(1 + MAX_VARINT_SIZE);
+    compressed.push(record[0] | COMPRESSED_FLAG);
+    put_varint(&mut compressed, payload.len() as u64);
+    compressed.extend_from_slice(&compression::compress(payload));
+
+    if compressed.len() < record.len() {
+        compressed
+    } else {
+        record
+    }
+}
+
+//Longest varint a u64 can take
+const MAX_VARINT_SIZE: usize = 10;
+
+fn put_varint(vec: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        vec.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    vec.push(value as u8);
+}
+
+/// Decodes a reassembled record into the batch it was written from, with every entry carrying
+/// its sequence number. `offset` is where the record starts in the log and is only used for
+/// error reporting.
+///
+/// Either every entry decodes or an error is returned, a batch is never handed out in part.
+pub fn decode(record: &[u8], offset: u64) -> Result<WriteBatch, LogError> {
+    let (sequence, entries) = decode_refs(Cow::Borrowed(record), offset)?;
+    let records = entries.into_iter().map(RecordRef::into_record).collect();
+    Ok(WriteBatch::from_records(sequence, records))
+}
+
+/// Same as [`decode`], but returns the sequence number of the batch and its entries pointing
+/// into `record` instead of copying them. Entries of a compressed record are always owned.
+pub(crate) fn decode_refs(
+    record: Cow<'_, [u8]>,
+    offset: u64,
+) -> Result<(SequenceNumber, Vec<RecordRef<'_>>), LogError> {
+    match record {
+        Cow::Borrowed(record) if !is_compressed(record) => decode_entries(record, offset),
+        record => {
+            let record = if is_compressed(&record) {
+                decompress_record(&record, offset)?
+            } else {
+                record.into_owned()
+            };
+            let (sequence, entries) = decode_entries(&record, offset)?;
+            let entries = entries.into_iter().map(RecordRef::into_owned).collect();
+            Ok((sequence, entries))
+        }
+    }
+}
+
+fn is_compressed(record: &[u8]) -> bool {
+    record
+        .first()
+        .is_some_and(|version| version & COMPRESSED_FLAG != 0)
+}
+
+fn decode_entries(
+    record: &[u8],
+    offset: u64,
+) -> Result<(SequenceNumber, Vec<RecordRef<'_>>), LogError> {
+    if record.is_empty() {
+        return Err(LogError::BadRecordLength { offset });
+    }
+    let version = record[0];
+    if version != FORMAT_VERSION && version != FIXED_LENGTH_VERSION {
+        return Err(LogError::UnsupportedVersion { offset, version });
+    }
+    if record.len() < BATCH_HEADER_SIZE {
+        return Err(LogError::BadRecordLength { offset });
+    }
+
+    let mut sequence: [u8; SEQUENCE_SIZE] = [0; SEQUENCE_SIZE];
+    sequence.copy_from_slice(&record[1..1 + SEQUENCE_SIZE]);
+    let mut count: [u8; COUNT_SIZE] = [0; COUNT_SIZE];
+    count.copy_from_slice(&record[1 + SEQUENCE_SIZE..BATCH_HEADER_SIZE]);
+    let sequence = u64::from_be_bytes(sequence);
+    let count = u32::from_be_bytes(count);
+
+    let mut entries = Entries {
+        record,
+        position: BATCH_HEADER_SIZE,
+        offset,
+        varint_lengths: version != FIXED_LENGTH_VERSION,
+    };
+    let mut records = Vec::with_capacity((count as usize).min(record.len()));
+    for i in 0..count {
+        records.push(entries.next_entry(sequence.wrapping_add(i as u64))?);
+    }
+    if entries.position != record.len() {
+        return Err(LogError::BadRecordLength { offset });
+    }
+
+    Ok((sequence, records))
+}
+
+// Undoes `compress_record`. Only current version records are ever compressed.
+fn decompress_record(record: &[u8], offset: u64) -> Result<Vec<u8>, LogError> {
+    let version = record[0] & !COMPRESSED_FLAG;
+    if version != FORMAT_VERSION {
+        return Err(LogError::UnsupportedVersion {
+            offset,
+            version: record[0],
+        });
+    }
+
+    let mut entries = Entries {
+        record,
+        position: 1,
+        offset,
+        varint_lengths: true,
+    };
+    let len = entries.read_size()?;
+    let block = &record[entries.position..];
+    //Refuse to allocate for a length no block of this size could expand to
+    if len > block.len().saturating_mul(MAX_COMPRESSION_RATIO) {
+        return Err(LogError::BadCompression { offset });
+    }
+
+    let payload = compression::decompress(block, len).ok_or(LogError::BadCompression { offset })?;
+    let mut decompressed = Vec::with_capacity(1 + payload.len());
+    decompressed.push(version);
+    decompressed.extend_from_slice(&payload);
+    Ok(decompressed)
+}
+
+// Cursor over the entries of a batch record.
+struct Entries<'a> {
+    record: &'a [u8],
+    position: usize,
+    offset: u64,
+    varint_lengths: bool,
+}
+
+impl<'a> Entries<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LogError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.record.len())
+            .ok_or(LogError::BadRecordLength {
+                offset: self.offset,
+            })?;
+        let bytes = &self.record[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_size(&mut self) -> Result<usize, LogError> {
+        if !self.varint_lengths {
+            let mut size_buff: [u8; KEY_SIZE] = [0; KEY_SIZE];
+            size_buff.copy_from_slice(self.take(KEY_SIZE)?);
+            return Ok(KeyType::from_be_bytes(size_buff) as usize);
+        }
+
+        let mut value: u64 = 0;
+        for i in 0..MAX_VARINT_SIZE {
+            let byte = self.take(1)?[0];
+            let bits = (byte & 0x7F) as u64;
+            //The tenth byte only has room for the top bit of a u64
+            if i == MAX_VARINT_SIZE - 1 && bits > 1 {
+                break;
+            }
+            value |= bits << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value as usize);
+            }
+        }
+        Err(LogError::BadRecordLength {
+            offset: self.offset,
+        })
+    }
+
+    fn next_entry(&mut self, sequence: SequenceNumber) -> Result<RecordRef<'a>, LogError> {
+        const WRITE: u8 = OpCode::Write as u8;
+        const DELETE: u8 = OpCode::Delete as u8;
+
+        match self.take(1)?[0] {
+            WRITE => {
+                let key_length = self.read_size()?;
+                let val_length = self.read_size()?;
+                let key = self.take(key_length)?;
+                let val = self.take(val_length)?;
+                Ok(RecordRef::new(
+                    self.offset,
+                    sequence,
+                    key.into(),
+                    Some(val.into()),
+                ))
+            }
+            DELETE => {
+                let key_length = self.read_size()?;
+                let key = self.take(key_length)?;
+                Ok(RecordRef::new(self.offset, sequence, key.into(), None))
+            }
+            opcode => Err(LogError::BadOpCode {
+                offset: self.offset,
+                opcode,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn read_varint(bytes: &[u8]) -> Result<usize, LogError> {
+        Entries {
+            record: bytes,
+            position: 0,
+            offset: 0,
+            varint_lengths: true,
+        }
+        .read_size()
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, 16383, 16384, u32::MAX as u64, u64::MAX] {
+            let mut vec = vec![];
+            put_varint(&mut vec, value);
+            assert_eq!(read_varint(&vec).unwrap() as u64, value);
+        }
+
+        let mut vec = vec![];
+        put_varint(&mut vec, 300);
+        assert_eq!(vec, [0xAC, 0x02]);
+        vec.clear();
+        put_varint(&mut vec, u64::MAX);
+        assert_eq!(vec.len(), MAX_VARINT_SIZE);
+    }
+
+    fn large_batch() -> WriteBatch {
+        let mut batch = WriteBatch::new();
+        batch.set_sequence(7);
+        for i in 0..20 {
+            let doc = format!(
+                "{{\"id\": {}, \"name\": \"foo\", \"tags\": [\"a\", \"b\"]}}",
+                i
+            );
+            batch.put(format!("doc-{}", i).as_bytes(), doc.as_bytes());
+        }
+        batch.delete(b"doc-0");
+        batch
+    }
+
+    fn entries(batch: &WriteBatch) -> Vec<(SequenceNumber, Vec<u8>, Option<Vec<u8>>)> {
+        batch
+            .iter()
+            .map(|rec| {
+                (
+                    rec.sequence(),
+                    rec.key().to_vec(),
+                    rec.value().map(<[u8]>::to_vec),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let batch = large_batch();
+        let record = encode_batch(&batch);
+
+        let compressed = compress_record(record.clone(), Compression::Lz4 { min_size: 64 });
+        assert_eq!(compressed[0], FORMAT_VERSION | COMPRESSED_FLAG);
+        assert!(compressed.len() < record.len() / 2);
+
+        let decoded = decode(&compressed, 0).unwrap();
+        assert_eq!(decoded.sequence(), 7);
+        assert_eq!(entries(&decoded), entries(&batch));
+    }
+
+    #[test]
+    fn test_records_are_compressed_only_when_worth_it() {
+        let record = encode_batch(&large_batch());
+        assert_eq!(compress_record(record.clone(), Compression::None), record);
+        let min_size = record.len() + 1;
+        assert_eq!(
+            compress_record(record.clone(), Compression::Lz4 { min_size }),
+            record
+        );
+
+        let mut batch = WriteBatch::new();
+        let mut rng = rand::thread_rng();
+        let noise: Vec<u8> = (0..200).map(|_| rng.gen()).collect();
+        batch.set_sequence(0x0102_0304_0506_0708);
+        batch.put(b"k", &noise);
+        let record = encode_batch(&batch);
+        assert_eq!(
+            compress_record(record.clone(), Compression::Lz4 { min_size: 0 }),
+            record
+        );
+    }
+
+    #[test]
+    fn test_bad_compressed_records_are_rejected() {
+        let record = encode_batch(&large_batch());
+        let compressed = compress_record(record, Compression::Lz4 { min_size: 0 });
+
+        let truncated = &compressed[..compressed.len() - 1];
+        assert!(matches!(
+            decode(truncated, 9),
+            Err(LogError::BadCompression { offset: 9 })
+        ));
+
+        //Claims to expand to far more than the block could
+        let mut huge = vec![FORMAT_VERSION | COMPRESSED_FLAG];
+        put_varint(&mut huge, u32::MAX as u64);
+        huge.extend_from_slice(&[0x10, b'a']);
+        assert!(matches!(
+            decode(&huge, 0),
+            Err(LogError::BadCompression { .. })
+        ));
+
+        let mut old = compressed.clone();
+        old[0] = FIXED_LENGTH_VERSION | COMPRESSED_FLAG;
+        assert!(matches!(
+            decode(&old, 0),
+            Err(LogError::UnsupportedVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn test_bad_varints_are_rejected() {
+        //Runs off the end of the record
+        assert!(matches!(
+            read_varint(&[0x80, 0x80]),
+            Err(LogError::BadRecordLength { .. })
+        ));
+        //Too long for a u64
+        assert!(matches!(
+            read_varint(&[0xFF; 11]),
+            Err(LogError::BadRecordLength { .. })
+        ));
+        let mut overflow = [0xFF; 10];
+        overflow[9] = 0x02;
+        assert!(matches!(
+            read_varint(&overflow),
+            Err(LogError::BadRecordLength { .. })
+        ));
+    }
+}
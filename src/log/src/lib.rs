@@ -1,204 +1,255 @@
-
-
-use std::fs::File;
-use std::io;
-use std::io::{BufReader, Read, Write};
-use std::mem;
-use std::path::Path;
-
-//Both of the enums below constitute log record format
+mod batch;
+mod compression;
+mod concurrent;
+mod crc32c;
+mod error;
+mod format;
+mod manager;
+mod mapped;
+mod reader;
+mod recovery;
+mod sync;
+#[cfg(test)]
+mod testing;
+mod writer;
+
+pub use batch::WriteBatch;
+pub use compression::Compression;
+pub use concurrent::{ConcurrentLogWriter, WriteHandle};
+pub use error::LogError;
+pub use format::{FragmentType, BLOCK_SIZE, FORMAT_VERSION, HEADER_SIZE};
+pub use manager::{list_segments, segment_path, LogManager, LogOptions, DEFAULT_SEGMENT_SIZE};
+pub use mapped::{MappedLog, MappedRecords};
+pub use memtable::SequenceNumber;
+pub use reader::LogReader;
+pub use recovery::{recover, recover_segments, repair, RecoveryPolicy, RecoveryStats};
+pub use sync::{SyncMode, SyncPoint};
+pub use writer::LogWriter;
+
+use std::borrow::Cow;
+
+//Both of the enums below constitute log record format, see `format` for how they are laid out on disk
 
 pub enum OpCode {
     Write = 0,
     Delete = 1,
 }
 
+//The second field is the sequence number of the write, see `WriteBatch` for how they are assigned
 pub enum Record {
-    TypeValue(OpCode, usize, usize, String, String),
-    TypeDelete(OpCode, usize, String),
-}
-
-pub struct LogWriter {
-    writer: File,
+    TypeValue(OpCode, SequenceNumber, usize, usize, Vec<u8>, Vec<u8>),
+    TypeDelete(OpCode, SequenceNumber, usize, Vec<u8>),
 }
 
-type KeyType = u64;
-
-impl LogWriter {
-    pub fn new<P: AsRef<Path>>(path: P) -> LogWriter {
-        LogWriter {
-            writer: File::create(path).unwrap(),
+impl Record {
+    pub fn sequence(&self) -> SequenceNumber {
+        match self {
+            Record::TypeValue(_, seq, ..) | Record::TypeDelete(_, seq, ..) => *seq,
         }
     }
 
-    pub fn put(&mut self, key: &str, val: &str) -> io::Result<usize> {
-        let mut vec: Vec<u8> = Vec::new();
-
-        vec.push(OpCode::Write as u8);
-        vec.append(&mut (key.len() as KeyType).to_be_bytes().to_vec());
-        vec.append(&mut (val.len() as KeyType).to_be_bytes().to_vec());
-        vec.append(&mut key.as_bytes().to_vec());
-        vec.append(&mut val.as_bytes().to_vec());
-
-        self.writer.write_all(&vec)?;
-        self.writer.flush()?; //Even though writer puts all bytes in file instaneously. Still for extra surity flush is called.
-
-        Ok(vec.len())
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Record::TypeValue(_, _, _, _, key, _) | Record::TypeDelete(_, _, _, key) => key,
+        }
     }
 
-    pub fn delete(&mut self, key: &str) -> io::Result<usize> {
-        let mut vec: Vec<u8> = Vec::new();
-
-        vec.push(OpCode::Delete as u8);
-        vec.append(&mut (key.len() as KeyType).to_be_bytes().to_vec());
-        vec.append(&mut key.as_bytes().to_vec());
-
-        self.writer.write_all(&vec)?;
-        self.writer.flush()?; //Even though writer puts all bytes in file instaneously. Still for extra surity flush is called.
+    /// Value written by a put, `None` for a delete.
+    pub fn value(&self) -> Option<&[u8]> {
+        match self {
+            Record::TypeValue(_, _, _, _, _, val) => Some(val),
+            Record::TypeDelete(..) => None,
+        }
+    }
 
-        Ok(vec.len())
+    /// The key as a string, for logs that only ever hold UTF-8 keys.
+    pub fn key_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(self.key())
     }
 }
 
-pub struct LogReader {
-    buffer_reader: BufReader<File>,
+/// A [`Record`] read by [`MappedLog`]. Key and value point straight into the mapped log
+/// whenever the record was written in one piece, only records that span blocks or were
+/// compressed are copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordRef<'a> {
+    //Offset of the log record, and with it the batch, the entry was read from
+    offset: u64,
+    sequence: SequenceNumber,
+    key: Cow<'a, [u8]>,
+    value: Option<Cow<'a, [u8]>>,
 }
 
-impl LogReader {
-    const PAGE_SIZE: usize = 512;
-
-    pub fn new<P: AsRef<Path>>(path: P) -> LogReader {
-        let f = File::open(path).unwrap();
-        LogReader {
-            buffer_reader: BufReader::with_capacity(Self::PAGE_SIZE, f),
+impl<'a> RecordRef<'a> {
+    pub(crate) fn new(
+        offset: u64,
+        sequence: SequenceNumber,
+        key: Cow<'a, [u8]>,
+        value: Option<Cow<'a, [u8]>>,
+    ) -> RecordRef<'a> {
+        RecordRef {
+            offset,
+            sequence,
+            key,
+            value,
         }
     }
-}
-
-impl Iterator for LogReader {
-    type Item = Record;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut opcode: [u8; 1] = [0; 1];
-
-        self.buffer_reader.read_exact(&mut opcode[..]).unwrap();
 
-        const KEY_SIZE: usize = mem::size_of::<KeyType>();
-        let mut size_buff: [u8; KEY_SIZE] = [0; KEY_SIZE];
-
-        const WRITE: u8 = OpCode::Write as u8;
-        const DELETE: u8 = OpCode::Delete as u8;
-
-        match opcode[0] {
-            WRITE => {
-                self.buffer_reader.read_exact(&mut size_buff).unwrap();
-
-                let key_length = usize::from_be_bytes(size_buff);
-
-                self.buffer_reader.read_exact(&mut size_buff).unwrap();
+    /// Offset in the log of the record this entry was read from. Every entry of a batch
+    /// shares the offset of the batch.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
 
-                let val_length = usize::from_be_bytes(size_buff);
+    pub fn sequence(&self) -> SequenceNumber {
+        self.sequence
+    }
 
-                let mut buffer: Vec<u8> = vec![0; key_length];
-                self.buffer_reader.read_exact(&mut buffer).unwrap();
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
 
-                let key = std::str::from_utf8(&buffer).unwrap().to_string();
+    /// Value written by a put, `None` for a delete.
+    pub fn value(&self) -> Option<&[u8]> {
+        self.value.as_deref()
+    }
 
-                buffer.resize(val_length, 0);
-                self.buffer_reader.read_exact(&mut buffer).unwrap();
+    /// Whether key and value are borrowed from the log rather than copied out of it.
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.key, Cow::Borrowed(_)) && !matches!(self.value, Some(Cow::Owned(_)))
+    }
 
-                let val = std::str::from_utf8(&buffer).unwrap().to_string();
+    pub fn into_owned(self) -> RecordRef<'static> {
+        RecordRef {
+            offset: self.offset,
+            sequence: self.sequence,
+            key: Cow::Owned(self.key.into_owned()),
+            value: self.value.map(|value| Cow::Owned(value.into_owned())),
+        }
+    }
 
-                Some(Record::TypeValue(
+    pub fn into_record(self) -> Record {
+        let key = self.key.into_owned();
+        match self.value {
+            Some(value) => {
+                let value = value.into_owned();
+                Record::TypeValue(
                     OpCode::Write,
-                    key_length,
-                    val_length,
+                    self.sequence,
+                    key.len(),
+                    value.len(),
                     key,
-                    val,
-                ))
-            }
-            DELETE => {
-                self.buffer_reader.read_exact(&mut size_buff).unwrap();
-
-                let key_length = usize::from_be_bytes(size_buff);
-
-                let mut buffer: Vec<u8> = vec![0; key_length];
-                self.buffer_reader.read_exact(&mut buffer).unwrap();
-
-                let key = std::str::from_utf8(&buffer).unwrap().to_string();
-
-                Some(Record::TypeDelete(OpCode::Delete, key_length, key))
-            }
-            x => {
-                panic!("Wrong index is read. Value at index: {}", x);
+                    value,
+                )
             }
+            None => Record::TypeDelete(OpCode::Delete, self.sequence, key.len(), key),
         }
     }
-}#[cfg(test)]
-mod tests {
+}
 
+#[cfg(test)]
+mod tests {
     use super::*;
+    use crate::testing::log_path;
     use rand::Rng;
-    
-    
+    use std::fs::{File, OpenOptions};
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    //Fragment header, batch header and opcode of the first entry
+    const HEADER: usize = HEADER_SIZE + 1 + 8 + 4 + 1;
+
+    fn raw_fragment(fragment_type: FragmentType, data: &[u8]) -> Vec<u8> {
+        let checksum = crc32c::extend(crc32c::checksum(&[fragment_type as u8]), data);
+        let mut fragment = checksum.to_be_bytes().to_vec();
+        fragment.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        fragment.push(fragment_type as u8);
+        fragment.extend_from_slice(data);
+        fragment
+    }
 
     #[test]
     fn test_file_creation() {
-        let path = "./00001.log";
-        LogWriter::new(path);
-        let result = File::open(path);
+        let path = log_path("file_creation");
+        LogWriter::new(&path);
+        let result = File::open(&path);
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_write_size_to_file() {
-        let mut writer = LogWriter::new("./00001.log");
-        let size = writer.put("foo", "bar").unwrap();
+        let mut writer = LogWriter::new(log_path("write_size"));
+        let size = writer.put_str("foo", "bar").unwrap();
 
-        assert_eq!(size, 1 + 8 + 8 + 3 + 3);
+        assert_eq!(size, 7 + 1 + 8 + 4 + 1 + 1 + 1 + 3 + 3);
     }
 
     #[test]
     fn test_delete_size_to_file() {
-        let mut writer = LogWriter::new("./00001.log");
-        let size = writer.delete("foo").unwrap();
+        let mut writer = LogWriter::new(log_path("delete_size"));
+        let size = writer.delete_str("foo").unwrap();
 
-        assert_eq!(size, 1 + 8 + 3);
+        assert_eq!(size, 7 + 1 + 8 + 4 + 1 + 1 + 3);
+    }
+
+    #[test]
+    fn test_long_lengths_take_more_bytes() {
+        let mut writer = LogWriter::new(log_path("long_lengths"));
+        let key = "k".repeat(200);
+        let val = "v".repeat(20000);
+        let size = writer.put_str(&key, &val).unwrap();
+
+        assert_eq!(size, HEADER + 2 + 3 + 200 + 20000);
     }
 
     #[test]
     fn test_write_val_to_file() {
-        let path = "./00001.log";
-        let mut writer = LogWriter::new(path);
-        writer.put("foo", "bar").unwrap();
+        let path = log_path("write_val");
+        let mut writer = LogWriter::new(&path);
+        writer.put_str("foo", "bar").unwrap();
 
-        let mut f = File::open(path).unwrap();
+        let mut f = File::open(&path).unwrap();
 
-        let mut buffer = vec![0; 23];
+        let mut buffer = vec![0; HEADER + 8];
         f.read_exact(&mut buffer).unwrap();
 
-        assert_eq!(buffer[0], 0);
+        let mut slic: [u8; 4] = [0; 4];
+        slic.clone_from_slice(&buffer[0..4]);
+        assert_eq!(u32::from_be_bytes(slic), crc32c::checksum(&buffer[6..]));
 
-        let mut slic: [u8; 8] = [0; 8];
-        slic.clone_from_slice(&buffer[1..9]);
-        assert_eq!(usize::from_be_bytes(slic), 3);
+        let mut slic: [u8; 2] = [0; 2];
+        slic.clone_from_slice(&buffer[4..6]);
+        assert_eq!(u16::from_be_bytes(slic), 22);
+
+        assert_eq!(buffer[6], FragmentType::Full as u8);
+        assert_eq!(buffer[7], FORMAT_VERSION);
 
         let mut slic: [u8; 8] = [0; 8];
-        slic.clone_from_slice(&buffer[9..17]);
-        assert_eq!(usize::from_be_bytes(slic), 3);
+        slic.clone_from_slice(&buffer[8..16]);
+        assert_eq!(u64::from_be_bytes(slic), 1);
+
+        let mut slic: [u8; 4] = [0; 4];
+        slic.clone_from_slice(&buffer[16..20]);
+        assert_eq!(u32::from_be_bytes(slic), 1);
+
+        assert_eq!(buffer[20], 0);
+
+        let buffer = &buffer[HEADER..];
+
+        assert_eq!(buffer[0], 3);
+        assert_eq!(buffer[1], 3);
 
         let mut slic: Vec<u8> = vec![0; 3];
-        slic.clone_from_slice(&buffer[17..20]);
+        slic.clone_from_slice(&buffer[2..5]);
         assert_eq!(String::from_utf8(slic).unwrap(), "foo");
 
         let mut slic: Vec<u8> = vec![0; 3];
-        slic.clone_from_slice(&buffer[20..23]);
+        slic.clone_from_slice(&buffer[5..8]);
         assert_eq!(String::from_utf8(slic).unwrap(), "bar");
     }
 
     #[test]
     fn test_sequence_write_val_to_file() {
-        let path = "./00001.log";
+        let path = log_path("sequence_write_val");
 
         let sequence = [
             ("229427529247013", "9441423005"),
@@ -210,64 +261,60 @@ mod tests {
             ("71327", "8"),
         ];
 
-        let mut writer = LogWriter::new(path);
+        let mut writer = LogWriter::new(&path);
 
         for (key, val) in sequence.iter() {
-            writer.put(key, val).unwrap();
+            writer.put_str(key, val).unwrap();
         }
 
-        let mut f = File::open(path).unwrap();
+        let mut f = File::open(&path).unwrap();
 
         for (key, val) in sequence.iter() {
-            let mut buffer = vec![0; 1 + 8 + 8 + key.len() + val.len()];
+            let mut buffer = vec![0; HEADER + 1 + 1 + key.len() + val.len()];
             f.read_exact(&mut buffer).unwrap();
 
-            assert_eq!(buffer[0], 0);
-
-            let mut slic: [u8; 8] = [0; 8];
-            slic.clone_from_slice(&buffer[1..9]);
-            assert_eq!(usize::from_be_bytes(slic), key.len());
+            assert_eq!(buffer[HEADER - 1], 0);
+            let buffer = &buffer[HEADER..];
 
-            let mut slic: [u8; 8] = [0; 8];
-            slic.clone_from_slice(&buffer[9..17]);
-            assert_eq!(usize::from_be_bytes(slic), val.len());
+            assert_eq!(buffer[0] as usize, key.len());
+            assert_eq!(buffer[1] as usize, val.len());
 
-            let mut slic: Vec<u8> = vec![0; key.len()];
-            slic.clone_from_slice(&buffer[17..key.len()]);
+            let end = 2 + key.len();
+            let slic: Vec<u8> = buffer[2..end].to_vec();
             assert_eq!(String::from_utf8(slic).unwrap(), *key);
 
-            let begin = 17 + key.len();
-            let mut slic: Vec<u8> = vec![0; val.len()];
-            slic.clone_from_slice(&buffer[begin..val.len()]);
+            let slic: Vec<u8> = buffer[end..end + val.len()].to_vec();
             assert_eq!(String::from_utf8(slic).unwrap(), *val);
         }
     }
 
     #[test]
     fn test_delete_val_to_file() {
-        let path = "./00001.log";
-        let mut writer = LogWriter::new(path);
-        writer.delete("foo").unwrap();
+        let path = log_path("delete_val");
+        let mut writer = LogWriter::new(&path);
+        writer.delete_str("foo").unwrap();
 
-        let mut f = File::open(path).unwrap();
+        let mut f = File::open(&path).unwrap();
 
-        let mut buffer = vec![0; 12];
+        let mut buffer = vec![0; HEADER + 4];
         f.read_exact(&mut buffer).unwrap();
 
-        assert_eq!(buffer[0], 1);
+        assert_eq!(buffer[6], FragmentType::Full as u8);
+        assert_eq!(buffer[7], FORMAT_VERSION);
+        assert_eq!(buffer[HEADER - 1], 1);
 
-        let mut slic: [u8; 8] = [0; 8];
-        slic.clone_from_slice(&buffer[1..9]);
-        assert_eq!(usize::from_be_bytes(slic), 3);
+        let buffer = &buffer[HEADER..];
+
+        assert_eq!(buffer[0], 3);
 
         let mut slic: Vec<u8> = vec![0; 3];
-        slic.clone_from_slice(&buffer[9..12]);
+        slic.clone_from_slice(&buffer[1..4]);
         assert_eq!(String::from_utf8(slic).unwrap(), "foo");
     }
 
     #[test]
     fn test_sequence_delete_val_to_file() {
-        let path = "./00001.log";
+        let path = log_path("sequence_delete_val");
 
         let sequence = [
             "229427529247013",
@@ -286,33 +333,31 @@ mod tests {
             "8",
         ];
 
-        let mut writer = LogWriter::new(path);
+        let mut writer = LogWriter::new(&path);
 
         for key in sequence.iter() {
-            writer.delete(key).unwrap();
+            writer.delete_str(key).unwrap();
         }
 
-        let mut f = File::open(path).unwrap();
+        let mut f = File::open(&path).unwrap();
 
         for key in sequence.iter() {
-            let mut buffer = vec![0; 1 + 8 + 8 + key.len()];
+            let mut buffer = vec![0; HEADER + 1 + key.len()];
             f.read_exact(&mut buffer).unwrap();
 
-            assert_eq!(buffer[0], 0);
+            assert_eq!(buffer[HEADER - 1], 1);
+            let buffer = &buffer[HEADER..];
 
-            let mut slic: [u8; 8] = [0; 8];
-            slic.clone_from_slice(&buffer[1..9]);
-            assert_eq!(usize::from_be_bytes(slic), key.len());
+            assert_eq!(buffer[0] as usize, key.len());
 
-            let mut slic: Vec<u8> = vec![0; key.len()];
-            slic.clone_from_slice(&buffer[9..key.len()]);
+            let slic: Vec<u8> = buffer[1..1 + key.len()].to_vec();
             assert_eq!(String::from_utf8(slic).unwrap(), *key);
         }
     }
 
     #[test]
     fn test_iter_read_file() {
-        let path = "./00001.log";
+        let path = log_path("iter_read");
 
         let sequence = [
             ("229427529247013", "9441423005"),
@@ -324,7 +369,7 @@ mod tests {
             ("71327", "8"),
         ];
 
-        let mut writer = LogWriter::new(path);
+        let mut writer = LogWriter::new(&path);
 
         let mut rng = rand::thread_rng();
         let mut op_vec = vec![];
@@ -333,47 +378,586 @@ mod tests {
             let op_type: bool = rng.gen();
 
             if !op_type {
-                writer.put(key, val).unwrap();
+                writer.put_str(key, val).unwrap();
                 op_vec.push(OpCode::Write);
             } else {
-                writer.delete(key).unwrap();
+                writer.delete_str(key).unwrap();
                 op_vec.push(OpCode::Delete);
             }
         }
 
-        let reader = LogReader::new(path);
-
-        for (i, rec) in reader.enumerate() {
-            match op_vec[i] {
-                OpCode::Write => {
-                    let op_code: OpCode = OpCode::Delete;
-                    let key_len: usize = 0;
-                    let val_len: usize = 0;
-                    let key: String = "".to_string();
-                    let value: String = "".to_string();
-
-                    assert!(matches!(
-                        rec,
-                        Record::TypeValue(op_code, key_len, val_len, key, value)
-                    ));
+        let reader = LogReader::new(&path);
+        let records: Vec<Record> = reader.map(Result::unwrap).collect();
+        assert_eq!(records.len(), op_vec.len());
 
+        for (i, rec) in records.into_iter().enumerate() {
+            match (&op_vec[i], rec) {
+                (OpCode::Write, Record::TypeValue(op_code, seq, key_len, val_len, key, value)) => {
                     assert!(matches!(op_code, OpCode::Write));
+                    assert_eq!(seq, i as u64 + 1);
                     assert_eq!(key_len, sequence[i].0.len());
                     assert_eq!(val_len, sequence[i].1.len());
-                    assert_eq!(key, sequence[i].0);
-                    assert_eq!(value, sequence[i].1);
+                    assert_eq!(key, sequence[i].0.as_bytes());
+                    assert_eq!(value, sequence[i].1.as_bytes());
                 }
-                OpCode::Delete => {
-                    let op_code: OpCode = OpCode::Write;
-                    let key_len: usize = 0;
-                    let key: String = "".to_string();
-
-                    assert!(matches!(rec, Record::TypeDelete(op_code, key_len, key)));
-                    assert!(matches!(op_code, OpCode::Write));
+                (OpCode::Delete, Record::TypeDelete(op_code, seq, key_len, key)) => {
+                    assert!(matches!(op_code, OpCode::Delete));
+                    assert_eq!(seq, i as u64 + 1);
                     assert_eq!(key_len, sequence[i].0.len());
-                    assert_eq!(key, sequence[i].0);
+                    assert_eq!(key, sequence[i].0.as_bytes());
+                }
+                _ => panic!("Record {} has the wrong type", i),
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_sync_mode_writes_readable_log() {
+        let modes = [
+            SyncMode::None,
+            SyncMode::EveryWrite,
+            SyncMode::Interval(5),
+            SyncMode::GroupCommit,
+        ];
+
+        for (i, mode) in modes.iter().enumerate() {
+            let path = log_path(&format!("sync_mode_{}", i));
+            let mut writer = LogWriter::with_sync_mode(&path, *mode);
+            assert_eq!(writer.sync_mode(), *mode);
+
+            writer.put_str("foo", "bar").unwrap();
+            writer.delete_str("foo").unwrap();
+            writer.sync_point().wait().unwrap();
+            writer.sync().unwrap();
+            drop(writer);
+
+            let records: Vec<Record> = LogReader::new(&path).map(Result::unwrap).collect();
+            assert_eq!(records.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_failed_sync_leaves_log_readable() {
+        let path = log_path("failed_sync");
+        let mut writer = LogWriter::with_sync_mode(&path, SyncMode::Interval(60_000));
+        writer.put_str("a", "1").unwrap();
+
+        writer.fail_interval_sync(std::io::Error::other("sync failed"));
+        assert!(writer.put_str("b", &"x".repeat(BLOCK_SIZE / 2)).is_err());
+        //Large enough to cross block boundaries, which must be where the reader expects them
+        writer
+            .put_str("c", &"y".repeat(3 * BLOCK_SIZE / 2))
+            .unwrap();
+        drop(writer);
+
+        let keys: Vec<Vec<u8>> = LogReader::new(&path)
+            .map(|record| record.unwrap().key().to_vec())
+            .collect();
+        assert_eq!(keys, [b"a", b"c"]);
+    }
+
+    #[test]
+    fn test_group_commit_across_threads() {
+        let path = log_path("group_commit_threads");
+        let writer = std::sync::Mutex::new(LogWriter::with_sync_mode(&path, SyncMode::GroupCommit));
+
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let writer = &writer;
+                s.spawn(move || {
+                    for i in 0..25 {
+                        let point = {
+                            let mut writer = writer.lock().unwrap();
+                            writer.put_str(&format!("{}-{}", t, i), "val").unwrap();
+                            writer.sync_point()
+                        };
+                        point.wait().unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(LogReader::new(&path).map(Result::unwrap).count(), 100);
+    }
+
+    #[test]
+    fn test_write_batch_is_one_record() {
+        let path = log_path("write_batch");
+        let mut writer = LogWriter::new(&path);
+
+        writer.put_str("a", "1").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put_str("b", "2").delete_str("a").put_str("c", "3");
+        let size = writer.write(&mut batch).unwrap();
+        assert_eq!(writer.last_sequence(), 4);
+
+        assert_eq!(
+            size,
+            HEADER_SIZE + 1 + 8 + 4 + 3 * (1 + 1 + 1) + 2 * (1 + 1)
+        );
+        assert_eq!(writer.write(&mut WriteBatch::new()).unwrap(), 0);
+        assert_eq!(writer.last_sequence(), 4);
+
+        let keys: Vec<String> = LogReader::new(&path)
+            .map(|rec| {
+                let rec = rec.unwrap();
+                let key = rec.key_str().unwrap();
+                match rec.value() {
+                    Some(_) => format!("{}{}", key, rec.sequence()),
+                    None => format!("-{}{}", key, rec.sequence()),
                 }
+            })
+            .collect();
+        assert_eq!(keys, ["a1", "b2", "-a3", "c4"]);
+    }
+
+    #[test]
+    fn test_corrupt_batch_is_dropped_whole() {
+        let path = log_path("corrupt_batch");
+        let mut writer = LogWriter::new(&path);
+
+        let mut batch = WriteBatch::new();
+        for i in 0..10 {
+            batch.put_str(&format!("key-{}", i), "val");
+        }
+        writer.write(&mut batch).unwrap();
+
+        //Damage the last entry of the batch
+        let len = std::fs::metadata(&path).unwrap().len();
+        let mut f = OpenOptions::new().write(true).open(&path).unwrap();
+        f.seek(SeekFrom::Start(len - 1)).unwrap();
+        f.write_all(b"X").unwrap();
+
+        let mut reader = LogReader::new(&path);
+        assert!(matches!(
+            reader.next(),
+            Some(Err(LogError::ChecksumMismatch { offset: 0 }))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_reader_tracks_offset() {
+        let path = log_path("reader_offset");
+        let mut writer = LogWriter::new(&path);
+        let first = writer.put_str("foo", "bar").unwrap();
+        let second = writer.delete_str("foo").unwrap();
+
+        let mut reader = LogReader::new(&path);
+        assert_eq!(reader.offset(), 0);
+        reader.next().unwrap().unwrap();
+        assert_eq!(reader.offset(), first as u64);
+        reader.next().unwrap().unwrap();
+        assert_eq!(reader.offset(), (first + second) as u64);
+    }
+
+    #[test]
+    fn test_corrupt_record_is_detected() {
+        let path = log_path("corrupt_record");
+        let mut writer = LogWriter::new(&path);
+        let size = writer.put_str("foo", "bar").unwrap();
+        writer.put_str("baz", "qux").unwrap();
+        writer.delete_str("foo").unwrap();
+
+        //Flip a bit in the value of the second record
+        let mut f = OpenOptions::new().write(true).open(&path).unwrap();
+        f.seek(SeekFrom::Start((size + HEADER + 2 + 3) as u64))
+            .unwrap();
+        f.write_all(b"r").unwrap();
+
+        let mut reader = LogReader::new(&path);
+        assert!(matches!(reader.next(), Some(Ok(Record::TypeValue(..)))));
+        assert!(matches!(
+            reader.next(),
+            Some(Err(LogError::ChecksumMismatch { offset })) if offset == size as u64
+        ));
+        //Everything else in the block is dropped along with the corrupt record
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_large_record_spans_blocks() {
+        let path = log_path("large_record");
+        let large = "x".repeat(3 * BLOCK_SIZE);
+
+        let mut writer = LogWriter::new(&path);
+        writer.put_str("a", "1").unwrap();
+        let size = writer.put_str("b", &large).unwrap();
+        writer.put_str("c", "3").unwrap();
+
+        //Three full blocks of data need at least four fragments
+        assert!(size >= 3 * BLOCK_SIZE + 4 * HEADER_SIZE);
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes[6], FragmentType::Full as u8);
+        assert_eq!(bytes[HEADER + 2 + 2 + 6], FragmentType::First as u8);
+        assert_eq!(bytes[BLOCK_SIZE + 6], FragmentType::Middle as u8);
+        assert_eq!(bytes[2 * BLOCK_SIZE + 6], FragmentType::Middle as u8);
+        assert_eq!(bytes[3 * BLOCK_SIZE + 6], FragmentType::Last as u8);
+
+        let records: Vec<Record> = LogReader::new(&path).map(Result::unwrap).collect();
+        assert_eq!(records.len(), 3);
+        match &records[1] {
+            Record::TypeValue(_, _, _, val_len, key, val) => {
+                assert_eq!(key, b"b");
+                assert_eq!(*val_len, large.len());
+                assert_eq!(*val, large.as_bytes());
+            }
+            _ => panic!("Expected a write"),
+        }
+    }
+
+    #[test]
+    fn test_block_trailer_is_padded() {
+        let path = log_path("block_trailer");
+
+        //Leave three bytes at the end of the first block, too few for another header
+        //A value this long takes three bytes for its length
+        let val = "v".repeat(BLOCK_SIZE - 3 - HEADER - 1 - 3 - 1);
+        let mut writer = LogWriter::new(&path);
+        assert_eq!(writer.put_str("k", &val).unwrap(), BLOCK_SIZE - 3);
+        assert_eq!(writer.delete_str("k").unwrap(), 3 + HEADER + 1 + 1);
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[BLOCK_SIZE - 3..BLOCK_SIZE], &[0, 0, 0]);
+        assert_eq!(bytes[BLOCK_SIZE + 6], FragmentType::Full as u8);
+
+        let records: Vec<Record> = LogReader::new(&path).map(Result::unwrap).collect();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(records[1], Record::TypeDelete(..)));
+    }
+
+    #[test]
+    fn test_reader_resyncs_after_corruption() {
+        let path = log_path("resync");
+        let mut writer = LogWriter::new(&path);
+
+        //Physical start of every record, which moves past the trailer when a block is padded
+        let mut starts = vec![];
+        let mut end = 0;
+        for i in 0..2000 {
+            let key = format!("key-{}", i);
+            let size = writer.put_str(&key, &"v".repeat(i % 97)).unwrap();
+            let leftover = BLOCK_SIZE - end % BLOCK_SIZE;
+            starts.push(if leftover < HEADER_SIZE {
+                end + leftover
+            } else {
+                end
+            });
+            end += size;
+        }
+        assert!(end > 3 * BLOCK_SIZE);
+
+        let mut f = OpenOptions::new().write(true).open(&path).unwrap();
+        f.seek(SeekFrom::Start(HEADER as u64 + 2)).unwrap();
+        f.write_all(b"X").unwrap();
+
+        let mut reader = LogReader::new(&path);
+        assert!(matches!(
+            reader.next(),
+            Some(Err(LogError::ChecksumMismatch { offset: 0 }))
+        ));
+
+        let mut keys = vec![];
+        for rec in reader {
+            match rec {
+                Ok(Record::TypeValue(_, _, _, _, key, _)) => keys.push(key),
+                Ok(_) => panic!("Only writes were logged"),
+                //The tail of a record that started in the corrupt block
+                Err(LogError::FragmentOutOfOrder { .. }) => assert!(keys.is_empty()),
+                Err(err) => panic!("Unexpected error: {}", err),
             }
         }
+
+        let first = starts.iter().position(|&s| s >= BLOCK_SIZE).unwrap();
+        let expected: Vec<Vec<u8>> = (first..2000)
+            .map(|i| format!("key-{}", i).into_bytes())
+            .collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_empty_log_ends_cleanly() {
+        let path = log_path("empty_log");
+        LogWriter::new(&path);
+
+        let mut reader = LogReader::new(&path);
+        assert!(reader.next().is_none());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_many_empty_batches_are_skipped() {
+        let path = log_path("many_empty_batches");
+        let mut writer = LogWriter::new(&path);
+        let empty = format::encode_batch(&WriteBatch::new());
+        writer.add_records(&vec![&empty[..]; 200_000]).unwrap();
+        writer.put_str("foo", "bar").unwrap();
+
+        let mut reader = LogReader::new(&path);
+        assert!(matches!(reader.next(), Some(Ok(Record::TypeValue(..)))));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_truncated_record_is_reported() {
+        let path = log_path("truncated_record");
+        let mut writer = LogWriter::new(&path);
+        let size = writer.put_str("foo", "bar").unwrap();
+        writer.put_str("baz", "qux").unwrap();
+
+        //Every possible torn write of the second record
+        for cut in (1..size).rev() {
+            let f = OpenOptions::new().write(true).open(&path).unwrap();
+            f.set_len((size + cut) as u64).unwrap();
+
+            let mut reader = LogReader::new(&path);
+            assert!(matches!(reader.next(), Some(Ok(_))));
+            assert!(matches!(
+                reader.next(),
+                Some(Err(LogError::UnexpectedEof { offset })) if offset == size as u64
+            ));
+            assert!(reader.next().is_none());
+        }
+    }
+
+    #[test]
+    fn test_create_new_refuses_existing_log() {
+        let path = log_path("create_new");
+        let _ = std::fs::remove_file(&path);
+
+        let mut writer = LogWriter::create_new(&path).unwrap();
+        writer.put_str("foo", "bar").unwrap();
+        drop(writer);
+
+        let err = LogWriter::create_new(&path).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(LogReader::new(&path).count(), 1);
+    }
+
+    #[test]
+    fn test_open_append_continues_log() {
+        let path = log_path("open_append");
+        let mut writer = LogWriter::new(&path);
+        writer.put_str("a", "1").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put_str("b", "2").delete_str("a");
+        writer.write(&mut batch).unwrap();
+        drop(writer);
+
+        let mut writer = LogWriter::open_append(&path).unwrap();
+        assert_eq!(writer.last_sequence(), 3);
+        assert_eq!(writer.offset(), std::fs::metadata(&path).unwrap().len());
+        writer.put_str("c", "3").unwrap();
+
+        let records: Vec<(Vec<u8>, u64)> = LogReader::new(&path)
+            .map(Result::unwrap)
+            .map(|rec| (rec.key().to_vec(), rec.sequence()))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                (b"a".to_vec(), 1),
+                (b"b".to_vec(), 2),
+                (b"a".to_vec(), 3),
+                (b"c".to_vec(), 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_open_append_truncates_torn_tail() {
+        let path = log_path("open_append_torn");
+        let mut writer = LogWriter::new(&path);
+        let first = writer.put_str("a", "1").unwrap();
+        //Spans two blocks, so the tear can land in either fragment
+        let large = "x".repeat(BLOCK_SIZE);
+        let size = writer.put_str("b", &large).unwrap();
+        drop(writer);
+
+        for cut in [1, HEADER_SIZE + 2, BLOCK_SIZE - first, size - 1] {
+            let f = OpenOptions::new().write(true).open(&path).unwrap();
+            f.set_len((first + cut) as u64).unwrap();
+
+            let mut writer = LogWriter::open_append(&path).unwrap();
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), first as u64);
+            assert_eq!(writer.last_sequence(), 1);
+            writer.put_str("b", &large).unwrap();
+
+            let records: Vec<Record> = LogReader::new(&path).map(Result::unwrap).collect();
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[1].sequence(), 2);
+            assert_eq!(records[1].value(), Some(large.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_open_append_drops_zeroed_tail() {
+        let path = log_path("open_append_zeroes");
+        let mut writer = LogWriter::new(&path);
+        let size = writer.put_str("a", "1").unwrap();
+        drop(writer);
+
+        //Space the file system allocated but the crash kept from being written
+        let f = OpenOptions::new().write(true).open(&path).unwrap();
+        f.set_len(size as u64 + 100).unwrap();
+
+        let mut writer = LogWriter::open_append(&path).unwrap();
+        writer.put_str("b", "2").unwrap();
+
+        let keys: Vec<Vec<u8>> = LogReader::new(&path)
+            .map(|rec| rec.unwrap().key().to_vec())
+            .collect();
+        assert_eq!(keys, [b"a".to_vec(), b"b".to_vec()]);
+    }
+
+    #[test]
+    fn test_fixed_length_records_are_still_read() {
+        let path = log_path("fixed_lengths");
+
+        //A put and a delete as written before lengths were varints
+        let mut record = vec![1];
+        record.extend_from_slice(&7u64.to_be_bytes());
+        record.extend_from_slice(&2u32.to_be_bytes());
+        record.push(OpCode::Write as u8);
+        record.extend_from_slice(&3u64.to_be_bytes());
+        record.extend_from_slice(&3u64.to_be_bytes());
+        record.extend_from_slice(b"foobar");
+        record.push(OpCode::Delete as u8);
+        record.extend_from_slice(&3u64.to_be_bytes());
+        record.extend_from_slice(b"foo");
+        std::fs::write(&path, raw_fragment(FragmentType::Full, &record)).unwrap();
+
+        let mut writer = LogWriter::open_append(&path).unwrap();
+        assert_eq!(writer.last_sequence(), 8);
+        writer.put_str("baz", "qux").unwrap();
+
+        let records: Vec<Record> = LogReader::new(&path).map(Result::unwrap).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].key(), b"foo");
+        assert_eq!(records[0].value(), Some(&b"bar"[..]));
+        assert_eq!(records[1].sequence(), 8);
+        assert_eq!(records[1].value(), None);
+        assert_eq!(records[2].key(), b"baz");
+        assert_eq!(records[2].sequence(), 9);
+    }
+
+    #[test]
+    fn test_unknown_opcode_is_reported() {
+        let path = log_path("unknown_opcode");
+
+        let mut record = vec![FORMAT_VERSION];
+        record.extend_from_slice(&1u64.to_be_bytes());
+        record.extend_from_slice(&1u32.to_be_bytes());
+        record.push(7);
+
+        let mut batch = WriteBatch::new();
+        batch.delete_str("foo");
+        batch.set_sequence(2);
+        let mut bytes = raw_fragment(FragmentType::Full, &record);
+        bytes.append(&mut raw_fragment(
+            FragmentType::Full,
+            &format::encode_batch(&batch),
+        ));
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut reader = LogReader::new(&path);
+        assert!(matches!(
+            reader.next(),
+            Some(Err(LogError::BadOpCode {
+                offset: 0,
+                opcode: 7
+            }))
+        ));
+        assert!(matches!(reader.next(), Some(Ok(Record::TypeDelete(..)))));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_binary_keys_and_values() {
+        let path = log_path("binary");
+        let mut writer = LogWriter::new(&path);
+
+        let key = [0xC3, 0x28, 0x00, 0xFF];
+        let val: Vec<u8> = (0..=255).collect();
+        writer.put(&key, &val).unwrap();
+        writer.delete(&key).unwrap();
+        writer.put(b"", b"").unwrap();
+
+        let records: Vec<Record> = LogReader::new(&path).map(Result::unwrap).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].key(), key);
+        assert_eq!(records[0].value(), Some(&val[..]));
+        assert!(records[0].key_str().is_err());
+        assert_eq!(records[1].key(), key);
+        assert_eq!(records[1].value(), None);
+        assert_eq!(records[2].key(), b"");
+        assert_eq!(records[2].value(), Some(&b""[..]));
+    }
+
+    #[test]
+    fn test_compressed_records_are_read_transparently() {
+        let path = log_path("compressed");
+        let mut writer = LogWriter::new(&path);
+        writer.set_compression(Compression::Lz4 { min_size: 256 });
+
+        let doc = "{\"name\": \"foo\", \"tags\": [\"a\", \"b\"], \"active\": true}".repeat(40);
+        let size = writer.put(b"doc", doc.as_bytes()).unwrap();
+        assert!(size < doc.len() / 5);
+        //Below the threshold, written as it is
+        writer.put_str("foo", "bar").unwrap();
+        //Spans blocks even compressed
+        let mut rng = rand::thread_rng();
+        let large: Vec<u8> = (0..3 * BLOCK_SIZE)
+            .map(|_| rng.gen_range(b'a', b'e'))
+            .collect();
+        writer.put(b"large", &large).unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes[HEADER_SIZE], FORMAT_VERSION | 0x80);
+        assert_eq!(bytes[size + HEADER_SIZE], FORMAT_VERSION);
+
+        let records: Vec<Record> = LogReader::new(&path).map(Result::unwrap).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].value(), Some(doc.as_bytes()));
+        assert_eq!(records[1].value(), Some(&b"bar"[..]));
+        assert_eq!(records[2].value(), Some(&large[..]));
+        assert_eq!(records[2].sequence(), 3);
+    }
+
+    #[test]
+    fn test_orphan_fragments_are_reported() {
+        let path = log_path("orphan_fragments");
+
+        let mut batch = WriteBatch::new();
+        batch.put_str("foo", "bar");
+        let record = format::encode_batch(&batch);
+        let (head, tail) = record.split_at(5);
+        let mut bytes = raw_fragment(FragmentType::Last, tail);
+        bytes.append(&mut raw_fragment(FragmentType::First, head));
+        bytes.append(&mut raw_fragment(FragmentType::Full, &record));
+        bytes.append(&mut raw_fragment(FragmentType::First, head));
+        std::fs::write(&path, &bytes).unwrap();
+
+        let second = (HEADER_SIZE + tail.len()) as u64;
+        let last = second + (2 * HEADER_SIZE + head.len() + record.len()) as u64;
+
+        let mut reader = LogReader::new(&path);
+        assert!(matches!(
+            reader.next(),
+            Some(Err(LogError::FragmentOutOfOrder { offset: 0 }))
+        ));
+        assert!(matches!(
+            reader.next(),
+            Some(Err(LogError::FragmentOutOfOrder { offset })) if offset == second
+        ));
+        assert!(matches!(reader.next(), Some(Ok(Record::TypeValue(..)))));
+        assert!(matches!(
+            reader.next(),
+            Some(Err(LogError::UnexpectedEof { offset })) if offset == last
+        ));
+        assert!(reader.next().is_none());
     }
 }
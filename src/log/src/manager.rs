@@ -0,0 +1,361 @@
+use crate::recovery::{recover_segments, RecoveryPolicy, RecoveryStats};
+use crate::{
+    Compression, LogError, LogWriter, MappedLog, SequenceNumber, SyncMode, SyncPoint, WriteBatch,
+};
+use memtable::Memtable;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Size after which [`LogManager`] moves on to a new segment unless told otherwise.
+pub const DEFAULT_SEGMENT_SIZE: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LogOptions {
+    /// A segment is closed once it has grown to at least this many bytes. Records are never
+    /// split across segments, so a segment can end up somewhat larger.
+    pub segment_size: u64,
+    pub sync_mode: SyncMode,
+    pub compression: Compression,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        LogOptions {
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            sync_mode: SyncMode::None,
+            compression: Compression::None,
+        }
+    }
+}
+
+/// Path of segment `number` inside `dir`, e.g. `dir/00001.log`.
+pub fn segment_path<P: AsRef<Path>>(dir: P, number: u64) -> PathBuf {
+    dir.as_ref().join(format!("{:05}.log", number))
+}
+
+// Segment number of a file name like `00001.log`.
+fn segment_number(name: &str) -> Option<u64> {
+    let number = name.strip_suffix(".log")?;
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    number.parse().ok()
+}
+
+/// Numbers of the segments in `dir`, oldest first.
+pub fn list_segments<P: AsRef<Path>>(dir: P) -> io::Result<Vec<u64>> {
+    let mut segments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if let Some(number) = entry.file_name().to_str().and_then(segment_number) {
+            segments.push(number);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+// Sequence number of the last write logged in `segments` of `dir`, 0 if there is none.
+// Sequence numbers only grow, so it is in the newest segment that holds any record at all.
+fn last_logged_sequence(dir: &Path, segments: &[u64]) -> io::Result<SequenceNumber> {
+    for &number in segments.iter().rev() {
+        let log = MappedLog::open(segment_path(dir, number))?;
+        let mut last_sequence = None;
+        for record in log.records() {
+            match record {
+                Ok(record) => last_sequence = last_sequence.max(Some(record.sequence())),
+                Err(LogError::Io(err)) => return Err(err),
+                //Corrupt records are for recovery to deal with
+                Err(_) => {}
+            }
+        }
+        if let Some(last_sequence) = last_sequence {
+            return Ok(last_sequence);
+        }
+    }
+    Ok(0)
+}
+
+/// Write ahead log spread over numbered segment files in one directory.
+///
+/// Writes go to the newest segment. Once it grows past [`LogOptions::segment_size`], or when
+/// [`LogManager::rotate`] is called because the memtable is being flushed, a new segment is
+/// started. Segments whose data has been persisted elsewhere are removed with
+/// [`LogManager::remove_segments_before`].
+///
+/// Opening a directory never touches the segments already in it, the first segment written
+/// is numbered after the newest one found and new writes get sequence numbers after the ones
+/// logged in them.
+pub struct LogManager {
+    dir: PathBuf,
+    options: LogOptions,
+    //Every segment in the directory, oldest first. The last one is being written to.
+    segments: Vec<u64>,
+    writer: LogWriter,
+}
+
+impl LogManager {
+    pub fn open<P: AsRef<Path>>(dir: P, options: LogOptions) -> io::Result<LogManager> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = list_segments(&dir)?;
+        let last_sequence = last_logged_sequence(&dir, &segments)?;
+        let number = segments.last().map_or(1, |last| last + 1);
+        let mut writer = LogWriter::create_new(segment_path(&dir, number))?;
+        writer.set_sync_mode(options.sync_mode);
+        writer.set_compression(options.compression);
+        writer.set_last_sequence(last_sequence);
+        segments.push(number);
+
+        Ok(LogManager {
+            dir,
+            options,
+            segments,
+            writer,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Numbers of all segments in the directory, oldest first. The last one is being written to.
+    pub fn segments(&self) -> &[u64] {
+        &self.segments
+    }
+
+    /// Number of the segment being written to.
+    pub fn current_segment(&self) -> u64 {
+        *self.segments.last().unwrap()
+    }
+
+    pub fn put(&mut self, key: &[u8], val: &[u8]) -> io::Result<usize> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, val);
+        self.write(&mut batch)
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<usize> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(&mut batch)
+    }
+
+    /// Appends `batch` to the current segment, see [`LogWriter::write`]. Moves on to a new
+    /// segment first if the current one is full.
+    ///
+    /// An error means `batch` was not logged, so the write can be retried. Failing to start a
+    /// new segment fails the write that needs it, not the one that filled the segment up.
+    pub fn write(&mut self, batch: &mut WriteBatch) -> io::Result<usize> {
+        if self.writer.offset() >= self.options.segment_size {
+            self.rotate()?;
+        }
+        self.writer.write(batch)
+    }
+
+    pub fn last_sequence(&self) -> SequenceNumber {
+        self.writer.last_sequence()
+    }
+
+    /// Replays every segment written before this manager was opened into `memtable`, see
+    /// [`recover_segments`]. New writes get sequence numbers after the ones recovered.
+    pub fn recover<M: Memtable>(
+        &mut self,
+        memtable: &M,
+        policy: RecoveryPolicy,
+    ) -> Result<RecoveryStats, LogError> {
+        let old = &self.segments[..self.segments.len() - 1];
+        let stats = recover_segments(&self.dir, old, memtable, policy)?;
+        if stats.last_sequence > self.writer.last_sequence() {
+            self.writer.set_last_sequence(stats.last_sequence);
+        }
+        Ok(stats)
+    }
+
+    /// See [`LogWriter::sync_point`]. A sync point taken before a rotation stays valid after it.
+    pub fn sync_point(&self) -> SyncPoint {
+        self.writer.sync_point()
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.writer.sync()
+    }
+
+    /// Closes the current segment and starts writing to a new one, whose number is returned.
+    /// Sequence numbers carry on where the closed segment left off.
+    ///
+    /// Called when the memtable is flushed, so that every segment before the returned one
+    /// only holds data of the flushed memtable.
+    pub fn rotate(&mut self) -> io::Result<u64> {
+        //Make sure the old segment is complete on disk before anything lands in the new one
+        if self.options.sync_mode != SyncMode::None {
+            self.writer.sync()?;
+        }
+
+        let number = self.current_segment() + 1;
+        let mut writer = LogWriter::create_new(segment_path(&self.dir, number))?;
+        writer.set_sync_mode(self.options.sync_mode);
+        writer.set_compression(self.options.compression);
+        writer.set_last_sequence(self.writer.last_sequence());
+
+        self.writer = writer;
+        self.segments.push(number);
+        Ok(number)
+    }
+
+    /// Deletes every segment numbered below `number`, once whatever they hold has been
+    /// persisted. The segment being written to is never deleted.
+    pub fn remove_segments_before(&mut self, number: u64) -> io::Result<()> {
+        let number = number.min(self.current_segment());
+        while self.segments[0] < number {
+            match fs::remove_file(segment_path(&self.dir, self.segments[0])) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+            self.segments.remove(0);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::log_dir;
+    use crate::{LogReader, Record};
+
+    fn read_keys(dir: &Path, segments: &[u64]) -> Vec<(Vec<u8>, SequenceNumber)> {
+        segments
+            .iter()
+            .flat_map(|&number| LogReader::new(segment_path(dir, number)))
+            .map(Result::unwrap)
+            .map(|rec: Record| (rec.key().to_vec(), rec.sequence()))
+            .collect()
+    }
+
+    #[test]
+    fn test_segment_names() {
+        assert_eq!(segment_path("logs", 7), Path::new("logs").join("00007.log"));
+        assert_eq!(segment_number("00007.log"), Some(7));
+        assert_eq!(segment_number("123456.log"), Some(123456));
+        assert_eq!(segment_number("00007.log.tmp"), None);
+        assert_eq!(segment_number("+7.log"), None);
+        assert_eq!(segment_number(".log"), None);
+    }
+
+    #[test]
+    fn test_open_never_clobbers_existing_segments() {
+        let dir = log_dir("manager_reopen");
+
+        let mut manager = LogManager::open(&dir, LogOptions::default()).unwrap();
+        assert_eq!(manager.segments(), [1]);
+        manager.put(b"a", b"1").unwrap();
+        drop(manager);
+
+        let mut manager = LogManager::open(&dir, LogOptions::default()).unwrap();
+        assert_eq!(manager.segments(), [1, 2]);
+        manager.put(b"b", b"2").unwrap();
+
+        assert_eq!(read_keys(&dir, &[1]), [(b"a".to_vec(), 1)]);
+        assert_eq!(list_segments(&dir).unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn test_open_carries_on_with_logged_sequences() {
+        let dir = log_dir("manager_reopen_sequence");
+
+        let mut manager = LogManager::open(&dir, LogOptions::default()).unwrap();
+        manager.put(b"a", b"1").unwrap();
+        manager.delete(b"b").unwrap();
+        drop(manager);
+        //Leaves an empty segment behind
+        drop(LogManager::open(&dir, LogOptions::default()).unwrap());
+
+        let mut manager = LogManager::open(&dir, LogOptions::default()).unwrap();
+        assert_eq!(manager.last_sequence(), 2);
+        manager.put(b"c", b"3").unwrap();
+
+        assert_eq!(
+            read_keys(&dir, manager.segments()),
+            [(b"a".to_vec(), 1), (b"b".to_vec(), 2), (b"c".to_vec(), 3)]
+        );
+    }
+
+    #[test]
+    fn test_rotates_on_segment_size() {
+        let dir = log_dir("manager_rotate");
+        let options = LogOptions {
+            segment_size: 1024,
+            ..LogOptions::default()
+        };
+        let mut manager = LogManager::open(&dir, options).unwrap();
+
+        for i in 0..100 {
+            manager
+                .put(format!("key-{:03}", i).as_bytes(), &[0; 50])
+                .unwrap();
+        }
+        assert!(manager.segments().len() > 5);
+        assert_eq!(list_segments(&dir).unwrap(), manager.segments());
+
+        //Every record made it to exactly one segment, with sequence numbers running on
+        let keys = read_keys(&dir, manager.segments());
+        let expected: Vec<(Vec<u8>, SequenceNumber)> = (0..100)
+            .map(|i| (format!("key-{:03}", i).into_bytes(), i + 1))
+            .collect();
+        assert_eq!(keys, expected);
+        for &number in &manager.segments()[..manager.segments().len() - 1] {
+            let size = fs::metadata(segment_path(&dir, number)).unwrap().len();
+            assert!((1024..1024 + 100).contains(&size));
+        }
+    }
+
+    #[test]
+    fn test_failed_rotation_fails_the_next_write() {
+        let dir = log_dir("manager_failed_rotate");
+        let options = LogOptions {
+            segment_size: 10,
+            ..LogOptions::default()
+        };
+        let mut manager = LogManager::open(&dir, options).unwrap();
+        //Stands in the way of the next segment
+        fs::write(segment_path(&dir, 2), b"").unwrap();
+
+        manager.put(b"a", b"1").unwrap();
+        assert_eq!(manager.segments(), [1]);
+        assert!(manager.put(b"b", b"2").is_err());
+        assert_eq!(manager.last_sequence(), 1);
+
+        fs::remove_file(segment_path(&dir, 2)).unwrap();
+        manager.put(b"b", b"2").unwrap();
+        assert_eq!(manager.segments(), [1, 2]);
+        assert_eq!(
+            read_keys(&dir, manager.segments()),
+            [(b"a".to_vec(), 1), (b"b".to_vec(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_flushed_segments_are_removed() {
+        let dir = log_dir("manager_remove");
+        let mut manager = LogManager::open(&dir, LogOptions::default()).unwrap();
+
+        manager.put(b"a", b"1").unwrap();
+        let flushed = manager.rotate().unwrap();
+        manager.put(b"b", b"2").unwrap();
+        assert_eq!(flushed, 2);
+        assert_eq!(manager.last_sequence(), 2);
+
+        manager.remove_segments_before(flushed).unwrap();
+        assert_eq!(manager.segments(), [2]);
+        assert_eq!(list_segments(&dir).unwrap(), [2]);
+        assert_eq!(read_keys(&dir, &[2]), [(b"b".to_vec(), 2)]);
+
+        //The segment being written to stays put
+        manager.remove_segments_before(10).unwrap();
+        assert_eq!(list_segments(&dir).unwrap(), [2]);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::format::{self, BLOCK_SIZE};
+use crate::reader::{scan_block, Fragment, Fragments, Reassembler, Scan};
+use crate::{LogError, RecordRef};
+use memmap2::Mmap;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// A log mapped into memory, for reading it back as fast as possible when replaying it.
+///
+/// Unlike [`crate::LogReader`] nothing is copied out of the log unless it has to be, the
+/// records handed out by [`MappedLog::records`] point straight into the mapping.
+///
+/// The mapping covers the log as it was when it was opened. Records appended afterwards are
+/// not seen, and the log must not be truncated or rewritten while it is mapped, as
+/// [`crate::LogWriter::open_append`] does to a torn tail. Reading a truncated mapping kills
+/// the process.
+pub struct MappedLog {
+    map: Mmap,
+}
+
+impl MappedLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedLog> {
+        let file = File::open(path)?;
+        //The log is only ever appended to while it is being written, see above
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(MappedLog { map })
+    }
+
+    /// Size of the log in bytes.
+    pub fn len(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterates over the records of the log, see [`crate::LogReader`] for how errors are
+    /// handled.
+    pub fn records(&self) -> MappedRecords<'_> {
+        MappedRecords {
+            reassembler: Reassembler::new(MappedBlocks {
+                log: &self.map,
+                block_start: 0,
+                position: 0,
+            }),
+            batch: VecDeque::new(),
+            finished: false,
+        }
+    }
+}
+
+struct MappedBlocks<'a> {
+    log: &'a [u8],
+    block_start: usize,
+    //Position of the next fragment inside the current block
+    position: usize,
+}
+
+impl<'a> Fragments<'a> for MappedBlocks<'a> {
+    fn next_fragment(&mut self) -> Result<Option<Fragment<'a>>, LogError> {
+        loop {
+            let block_end = self.log.len().min(self.block_start + BLOCK_SIZE);
+            let block = &self.log[self.block_start..block_end];
+            let last_block = block.len() < BLOCK_SIZE;
+
+            match scan_block(
+                block,
+                &mut self.position,
+                self.block_start as u64,
+                last_block,
+            ) {
+                Scan::Fragment(fragment) => return Ok(Some(fragment)),
+                Scan::EndOfBlock if last_block => return Ok(None),
+                Scan::EndOfBlock => {
+                    self.block_start = block_end;
+                    self.position = 0;
+                }
+                Scan::Error(err) => return Err(err),
+            }
+        }
+    }
+
+    fn offset(&self) -> u64 {
+        (self.block_start + self.position) as u64
+    }
+}
+
+/// Records of a [`MappedLog`], borrowed from it.
+pub struct MappedRecords<'a> {
+    reassembler: Reassembler<'a, MappedBlocks<'a>>,
+    //Rest of the batch the last record decoded to
+    batch: VecDeque<RecordRef<'a>>,
+    finished: bool,
+}
+
+impl MappedRecords<'_> {
+    /// Byte offset in the log where the next fragment will be looked for.
+    pub fn offset(&self) -> u64 {
+        self.reassembler.fragments.offset()
+    }
+}
+
+impl<'a> Iterator for MappedRecords<'a> {
+    type Item = Result<RecordRef<'a>, LogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.batch.pop_front() {
+                return Some(Ok(record));
+            }
+            if self.finished {
+                return None;
+            }
+
+            let batch = self
+                .reassembler
+                .read_record()
+                .and_then(|record| match record {
+                    Some((offset, data)) => format::decode_refs(data, offset).map(Some),
+                    None => Ok(None),
+                });
+
+            match batch {
+                Ok(Some((_, entries))) => self.batch.extend(entries),
+                Ok(None) => self.finished = true,
+                Err(err) => {
+                    self.finished = !err.is_recoverable();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::log_path;
+    use crate::{Compression, LogReader, LogWriter, Record, WriteBatch, HEADER_SIZE};
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    fn summary<'a>(
+        records: impl Iterator<Item = (u64, &'a [u8], Option<&'a [u8]>)>,
+    ) -> Vec<(u64, Vec<u8>, Option<Vec<u8>>)> {
+        records
+            .map(|(seq, key, val)| (seq, key.to_vec(), val.map(<[u8]>::to_vec)))
+            .collect()
+    }
+
+    #[test]
+    fn test_reads_what_log_reader_reads() {
+        let path = log_path("mapped_same");
+        let mut writer = LogWriter::new(&path);
+        writer.put(b"foo", b"bar").unwrap();
+        writer.delete(b"foo").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"a", b"1");
+        batch.put(b"b", &vec![b'x'; 3 * BLOCK_SIZE]);
+        writer.write(&mut batch).unwrap();
+        writer.set_compression(Compression::Lz4 { min_size: 0 });
+        writer.put(b"doc", &b"{\"a\": 1}".repeat(100)).unwrap();
+        writer.put(b"", b"").unwrap();
+
+        let log = MappedLog::open(&path).unwrap();
+        assert_eq!(log.len(), std::fs::metadata(&path).unwrap().len());
+        let mapped: Vec<RecordRef> = log.records().map(Result::unwrap).collect();
+        let read: Vec<Record> = LogReader::new(&path).map(Result::unwrap).collect();
+
+        assert_eq!(mapped.len(), 6);
+        assert_eq!(
+            summary(mapped.iter().map(|r| (r.sequence(), r.key(), r.value()))),
+            summary(read.iter().map(|r| (r.sequence(), r.key(), r.value())))
+        );
+
+        //Only the record spanning blocks and the compressed one had to be copied
+        let borrowed: Vec<bool> = mapped.iter().map(RecordRef::is_borrowed).collect();
+        assert_eq!(borrowed, [true, true, false, false, false, false]);
+    }
+
+    #[test]
+    fn test_empty_log() {
+        let path = log_path("mapped_empty");
+        LogWriter::new(&path);
+
+        let log = MappedLog::open(&path).unwrap();
+        assert!(log.is_empty());
+        assert!(log.records().next().is_none());
+    }
+
+    #[test]
+    fn test_errors_match_log_reader() {
+        let path = log_path("mapped_errors");
+        let mut writer = LogWriter::new(&path);
+        writer.put(b"a", b"1").unwrap();
+        writer.put(b"b", &vec![b'x'; BLOCK_SIZE]).unwrap();
+        writer.put(b"c", b"3").unwrap();
+        drop(writer);
+
+        let mut f = OpenOptions::new().write(true).open(&path).unwrap();
+        f.seek(SeekFrom::Start(HEADER_SIZE as u64)).unwrap();
+        f.write_all(b"X").unwrap();
+        let len = f.metadata().unwrap().len();
+        f.set_len(len - 1).unwrap();
+        drop(f);
+
+        let log = MappedLog::open(&path).unwrap();
+        let mapped: Vec<String> = log
+            .records()
+            .map(|r| format!("{:?}", r.map(|r| r.sequence())))
+            .collect();
+        let read: Vec<String> = LogReader::new(&path)
+            .map(|r| format!("{:?}", r.map(|r| r.sequence())))
+            .collect();
+        assert_eq!(mapped, read);
+        assert!(matches!(
+            log.records().next(),
+            Some(Err(LogError::ChecksumMismatch { offset: 0 }))
+        ));
+        assert!(matches!(
+            log.records().last(),
+            Some(Err(LogError::UnexpectedEof { .. }))
+        ));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::crc32c;
+use crate::format::{self, FragmentType, BLOCK_SIZE, HEADER_SIZE};
+use crate::{LogError, Record};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+pub(crate) struct Fragment<'a> {
+    fragment_type: FragmentType,
+    data: Cow<'a, [u8]>,
+    offset: u64,
+}
+
+// What `scan_block` found in the rest of a block.
+pub(crate) enum Scan<'a> {
+    Fragment(Fragment<'a>),
+    //Nothing left in this block, the next fragment starts in the next one if there is one
+    EndOfBlock,
+    Error(LogError),
+}
+
+// Looks for the next intact fragment in `block`, starting at `position` and moving it past
+// whatever was looked at. `block_start` is the offset of `block` in the log and `last_block`
+// whether the log ends with it.
+pub(crate) fn scan_block<'a>(
+    block: &'a [u8],
+    position: &mut usize,
+    block_start: u64,
+    last_block: bool,
+) -> Scan<'a> {
+    let offset = block_start + *position as u64;
+    let leftover = block.len() - *position;
+    if leftover < HEADER_SIZE {
+        //Either the end of the log or the block trailer, unless the log ends in the middle of
+        //a header
+        *position = block.len();
+        return if leftover == 0 || !last_block {
+            Scan::EndOfBlock
+        } else {
+            Scan::Error(LogError::UnexpectedEof { offset })
+        };
+    }
+
+    let header = &block[*position..*position + HEADER_SIZE];
+    let checksum = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let raw_type = header[6];
+
+    if raw_type == FragmentType::Zero as u8 && length == 0 {
+        //Zeroed space, nothing was ever written to the rest of this block
+        *position = block.len();
+        return Scan::EndOfBlock;
+    }
+
+    if HEADER_SIZE + length > leftover {
+        *position = block.len();
+        return Scan::Error(if last_block {
+            LogError::UnexpectedEof { offset }
+        } else {
+            LogError::BadRecordLength { offset }
+        });
+    }
+
+    let data_start = *position + HEADER_SIZE;
+    let data = &block[data_start..data_start + length];
+    if crc32c::extend(crc32c::checksum(&[raw_type]), data) != checksum {
+        //The length may be what got corrupted, so nothing else in this block can be trusted
+        *position = block.len();
+        return Scan::Error(LogError::ChecksumMismatch { offset });
+    }
+    *position = data_start + length;
+
+    match FragmentType::from_u8(raw_type) {
+        Some(fragment_type) if fragment_type != FragmentType::Zero => Scan::Fragment(Fragment {
+            fragment_type,
+            data: Cow::Borrowed(data),
+            offset,
+        }),
+        _ => Scan::Error(LogError::BadFragmentType {
+            offset,
+            fragment_type: raw_type,
+        }),
+    }
+}
+
+// Source of the fragments of a log, in the order they were written.
+pub(crate) trait Fragments<'a> {
+    // Returns the next intact fragment, `None` once the log ends cleanly.
+    fn next_fragment(&mut self) -> Result<Option<Fragment<'a>>, LogError>;
+
+    // Offset in the log where the next fragment is looked for.
+    fn offset(&self) -> u64;
+}
+
+// Offset and data of a reassembled record.
+pub(crate) type RawRecord<'a> = (u64, Cow<'a, [u8]>);
+
+// Puts records back together from their fragments.
+pub(crate) struct Reassembler<'a, F> {
+    pub(crate) fragments: F,
+    //Fragment read ahead while reassembling a record that turned out to be incomplete
+    pending: Option<Fragment<'a>>,
+}
+
+impl<'a, F: Fragments<'a>> Reassembler<'a, F> {
+    pub(crate) fn new(fragments: F) -> Reassembler<'a, F> {
+        Reassembler {
+            fragments,
+            pending: None,
+        }
+    }
+
+    // Reassembles the next record, returning its offset and data. A record that was written
+    // in one piece is handed out as the fragment source gave it.
+    pub(crate) fn read_record(&mut self) -> Result<Option<RawRecord<'a>>, LogError> {
+        //Offset of the first fragment and the data collected so far of a fragmented record
+        let mut partial: Option<(u64, Vec<u8>)> = None;
+
+        loop {
+            let fragment = match self.pending.take() {
+                Some(fragment) => fragment,
+                None => match self.fragments.next_fragment()? {
+                    Some(fragment) => fragment,
+                    None => {
+                        return match partial {
+                            Some((offset, _)) => Err(LogError::UnexpectedEof { offset }),
+                            None => Ok(None),
+                        };
+                    }
+                },
+            };
+
+            match (fragment.fragment_type, partial.as_mut()) {
+                (FragmentType::Full, None) => {
+                    return Ok(Some((fragment.offset, fragment.data)));
+                }
+                (FragmentType::First, None) => {
+                    partial = Some((fragment.offset, fragment.data.into_owned()));
+                }
+                (FragmentType::Middle, Some((_, data))) => {
+                    data.extend_from_slice(&fragment.data);
+                }
+                (FragmentType::Last, Some((_, data))) => {
+                    data.extend_from_slice(&fragment.data);
+                    return Ok(partial.map(|(offset, data)| (offset, Cow::Owned(data))));
+                }
+                (FragmentType::Full | FragmentType::First, Some((offset, _))) => {
+                    //The record being reassembled never got its last fragment. Report it and
+                    //start over with this fragment on the next call.
+                    let offset = *offset;
+                    self.pending = Some(fragment);
+                    return Err(LogError::FragmentOutOfOrder { offset });
+                }
+                (_, _) => {
+                    //Middle or last fragment whose beginning was lost
+                    return Err(LogError::FragmentOutOfOrder {
+                        offset: fragment.offset,
+                    });
+                }
+            }
+        }
+    }
+}
+
+// Reads the log a block at a time.
+struct FileBlocks {
+    file: File,
+    block: Vec<u8>,
+    //File offset of the first byte in `block`
+    block_start: u64,
+    //Position of the next fragment inside `block`
+    position: usize,
+    //Set once `block` holds the last, possibly partial, block of the file
+    last_block: bool,
+}
+
+impl FileBlocks {
+    fn read_block(&mut self) -> Result<(), LogError> {
+        self.block_start += self.block.len() as u64;
+        self.position = 0;
+        self.block.resize(BLOCK_SIZE, 0);
+
+        let mut filled = 0;
+        while filled < BLOCK_SIZE {
+            match self.file.read(&mut self.block[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.block.clear();
+                    return Err(LogError::Io(err));
+                }
+            }
+        }
+
+        self.block.truncate(filled);
+        self.last_block = filled < BLOCK_SIZE;
+        Ok(())
+    }
+}
+
+impl Fragments<'static> for FileBlocks {
+    fn next_fragment(&mut self) -> Result<Option<Fragment<'static>>, LogError> {
+        loop {
+            match scan_block(
+                &self.block,
+                &mut self.position,
+                self.block_start,
+                self.last_block,
+            ) {
+                Scan::Fragment(fragment) => {
+                    return Ok(Some(Fragment {
+                        fragment_type: fragment.fragment_type,
+                        data: Cow::Owned(fragment.data.into_owned()),
+                        offset: fragment.offset,
+                    }))
+                }
+                Scan::EndOfBlock if self.last_block => return Ok(None),
+                Scan::EndOfBlock => self.read_block()?,
+                Scan::Error(err) => return Err(err),
+            }
+        }
+    }
+
+    fn offset(&self) -> u64 {
+        self.block_start + self.position as u64
+    }
+}
+
+pub struct LogReader {
+    reassembler: Reassembler<'static, FileBlocks>,
+    //Rest of the batch the last record decoded to
+    batch: VecDeque<Record>,
+    finished: bool,
+}
+
+impl LogReader {
+    pub fn new<P: AsRef<Path>>(path: P) -> LogReader {
+        let f = File::open(path).unwrap();
+        let blocks = FileBlocks {
+            file: f,
+            block: Vec::with_capacity(BLOCK_SIZE),
+            block_start: 0,
+            position: 0,
+            last_block: false,
+        };
+        LogReader {
+            reassembler: Reassembler::new(blocks),
+            batch: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// Byte offset in the file where the reader will look for the next fragment.
+    pub fn offset(&self) -> u64 {
+        self.reassembler.fragments.offset()
+    }
+}
+
+/// Yields every record in the log in the order it was written and stops at the end of the log.
+/// A batch written with [`crate::LogWriter::write`] comes out as its individual records, which
+/// are only handed out once the whole batch has been read and verified.
+///
+/// Errors are handed to the caller instead of being acted upon. A corrupt fragment costs at
+/// most the rest of its block, after which the reader picks up again at the next block, so
+/// iteration can continue after any error except [`LogError::Io`]. A log that ends in the
+/// middle of a record yields [`LogError::UnexpectedEof`] and then ends.
+impl Iterator for LogReader {
+    type Item = Result<Record, LogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        //Empty batches are never written, but there is nothing to hand out for one either, so
+        //keep reading until a record turns up
+        loop {
+            if let Some(record) = self.batch.pop_front() {
+                return Some(Ok(record));
+            }
+            if self.finished {
+                return None;
+            }
+
+            let batch = self
+                .reassembler
+                .read_record()
+                .and_then(|record| match record {
+                    Some((offset, data)) => format::decode(&data, offset).map(Some),
+                    None => Ok(None),
+                });
+
+            match batch {
+                Ok(Some(batch)) => self.batch.extend(batch),
+                Ok(None) => self.finished = true,
+                Err(err) => {
+                    self.finished = !err.is_recoverable();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use crate::manager::{list_segments, segment_path};
+use crate::{LogError, MappedLog, SequenceNumber};
+use memtable::Memtable;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+/// What recovery does about a record it cannot read.
+///
+/// A log whose newest segment ends in the middle of a record is what a crash during a write
+/// leaves behind. That record was never acknowledged, so it is dropped under every policy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RecoveryPolicy {
+    /// Fail on any other unreadable record.
+    #[default]
+    Strict,
+    /// Recover everything up to the first unreadable record and nothing after it, so the
+    /// memtable ends up as it was at some point in time.
+    StopAtCorruption,
+    /// Skip unreadable records and recover everything else.
+    SkipCorrupt,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecoveryStats {
+    /// Segments that were read.
+    pub segments: usize,
+    /// Records replayed into the memtable.
+    pub records: u64,
+    /// Bytes of log up to the end of the last record replayed from each segment.
+    pub bytes: u64,
+    /// Errors that were skipped or that recovery stopped at, not counting a torn final write.
+    pub corrupt: u64,
+    /// Highest sequence number replayed, 0 if nothing was.
+    pub last_sequence: SequenceNumber,
+}
+
+/// Replays every segment in `dir` into `memtable`, oldest first. See [`recover_segments`].
+pub fn recover<P: AsRef<Path>, M: Memtable>(
+    dir: P,
+    memtable: &M,
+    policy: RecoveryPolicy,
+) -> Result<RecoveryStats, LogError> {
+    let segments = list_segments(&dir)?;
+    recover_segments(dir, &segments, memtable, policy)
+}
+
+/// Replays the records of `segments` in `dir` into `memtable` in the order they were written.
+/// The last segment is taken to be the one that was being written to when the log was closed.
+///
+/// On error the memtable holds whatever was replayed before it.
+pub fn recover_segments<P: AsRef<Path>, M: Memtable>(
+    dir: P,
+    segments: &[u64],
+    memtable: &M,
+    policy: RecoveryPolicy,
+) -> Result<RecoveryStats, LogError> {
+    let mut stats = RecoveryStats::default();
+
+    for (i, &number) in segments.iter().enumerate() {
+        let newest = i + 1 == segments.len();
+        //Segments are never truncated once closed and the newest one has no writer yet
+        let log = MappedLog::open(segment_path(&dir, number))?;
+        let mut records = log.records();
+        let mut end = 0;
+        stats.segments += 1;
+
+        while let Some(record) = records.next() {
+            let err = match record {
+                Ok(record) => {
+                    match record.value() {
+                        Some(val) => memtable.put(record.key(), val, record.sequence()),
+                        None => memtable.delete(record.key(), record.sequence()),
+                    }
+                    stats.records += 1;
+                    stats.last_sequence = stats.last_sequence.max(record.sequence());
+                    end = records.offset();
+                    continue;
+                }
+                Err(err) => err,
+            };
+
+            match (err, policy) {
+                //The write a crash interrupted, nothing can follow it
+                (LogError::UnexpectedEof { .. }, _) if newest => {}
+                (LogError::Io(err), _) => return Err(LogError::Io(err)),
+                (err, RecoveryPolicy::Strict) => return Err(err),
+                (_, RecoveryPolicy::StopAtCorruption) => {
+                    stats.corrupt += 1;
+                    stats.bytes += end;
+                    return Ok(stats);
+                }
+                (_, RecoveryPolicy::SkipCorrupt) => stats.corrupt += 1,
+            }
+        }
+
+        stats.bytes += end;
+    }
+
+    Ok(stats)
+}
+
+/// Cuts the log at `path` off right after the last intact record before the first one that
+/// cannot be read, for a log that recovery refuses. Everything from the corruption on is lost,
+/// including intact records after it. Returns the new length of the log, `None` if every
+/// record was intact and nothing was cut.
+pub fn repair<P: AsRef<Path>>(path: P) -> Result<Option<u64>, LogError> {
+    let path = path.as_ref();
+    let end = {
+        let log = MappedLog::open(path)?;
+        let mut records = log.records();
+        //End of the last intact record
+        let mut end = 0;
+        loop {
+            match records.next() {
+                None => return Ok(None),
+                Some(Ok(_)) => end = records.offset(),
+                Some(Err(LogError::Io(err))) => return Err(LogError::Io(err)),
+                Some(Err(_)) => break end,
+            }
+        }
+    };
+
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(end)?;
+    file.sync_all()?;
+    Ok(Some(end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{log_dir, MapMemtable};
+    use crate::{LogManager, LogOptions, HEADER_SIZE};
+    use std::fs::{self, OpenOptions};
+    use std::io::{Seek, SeekFrom, Write};
+    use std::path::PathBuf;
+
+    //Three segments with two writes each, the middle one holding a put and a delete of `b`
+    fn three_segments(name: &str) -> PathBuf {
+        let dir = log_dir(name);
+        let mut manager = LogManager::open(&dir, LogOptions::default()).unwrap();
+        manager.put(b"a", b"1").unwrap();
+        manager.put(b"b", b"2").unwrap();
+        manager.rotate().unwrap();
+        manager.put(b"c", b"3").unwrap();
+        manager.delete(b"b").unwrap();
+        manager.rotate().unwrap();
+        manager.put(b"d", b"4").unwrap();
+        manager.put(b"a", b"5").unwrap();
+        dir
+    }
+
+    fn corrupt(path: PathBuf, offset: u64) {
+        let mut f = OpenOptions::new().write(true).open(path).unwrap();
+        f.seek(SeekFrom::Start(offset)).unwrap();
+        f.write_all(b"X").unwrap();
+    }
+
+    #[test]
+    fn test_recovers_every_segment_in_order() {
+        let dir = three_segments("recover_all");
+
+        let memtable = MapMemtable::default();
+        let stats = recover(&dir, &memtable, RecoveryPolicy::Strict).unwrap();
+
+        assert_eq!(stats.segments, 3);
+        assert_eq!(stats.records, 6);
+        assert_eq!(stats.corrupt, 0);
+        assert_eq!(stats.last_sequence, 6);
+        let size: u64 = (1..=3)
+            .map(|n| fs::metadata(segment_path(&dir, n)).unwrap().len())
+            .sum();
+        assert_eq!(stats.bytes, size);
+
+        assert_eq!(memtable.get(b"a"), Some(b"5".to_vec()));
+        assert_eq!(memtable.get(b"b"), None);
+        assert_eq!(memtable.get(b"c"), Some(b"3".to_vec()));
+        assert_eq!(memtable.get(b"d"), Some(b"4".to_vec()));
+    }
+
+    #[test]
+    fn test_torn_final_write_is_dropped() {
+        let dir = three_segments("recover_torn");
+        let path = segment_path(&dir, 3);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let memtable = MapMemtable::default();
+        let stats = recover(&dir, &memtable, RecoveryPolicy::Strict).unwrap();
+
+        assert_eq!(stats.records, 5);
+        assert_eq!(stats.corrupt, 0);
+        assert_eq!(memtable.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(memtable.get(b"d"), Some(b"4".to_vec()));
+    }
+
+    #[test]
+    fn test_corruption_follows_the_policy() {
+        let dir = three_segments("recover_corrupt");
+        corrupt(segment_path(&dir, 2), HEADER_SIZE as u64);
+
+        let memtable = MapMemtable::default();
+        let err = recover(&dir, &memtable, RecoveryPolicy::Strict).err();
+        assert!(matches!(
+            err,
+            Some(LogError::ChecksumMismatch { offset: 0 })
+        ));
+
+        let memtable = MapMemtable::default();
+        let stats = recover(&dir, &memtable, RecoveryPolicy::StopAtCorruption).unwrap();
+        assert_eq!((stats.segments, stats.records, stats.corrupt), (2, 2, 1));
+        assert_eq!(stats.last_sequence, 2);
+        assert_eq!(memtable.get(b"b"), Some(b"2".to_vec()));
+        assert_eq!(memtable.get(b"d"), None);
+
+        //The rest of the block goes with the corrupt record, the delete of `b` included
+        let memtable = MapMemtable::default();
+        let stats = recover(&dir, &memtable, RecoveryPolicy::SkipCorrupt).unwrap();
+        assert_eq!((stats.segments, stats.records, stats.corrupt), (3, 4, 1));
+        assert_eq!(stats.last_sequence, 6);
+        assert_eq!(memtable.get(b"b"), Some(b"2".to_vec()));
+        assert_eq!(memtable.get(b"c"), None);
+        assert_eq!(memtable.get(b"a"), Some(b"5".to_vec()));
+    }
+
+    #[test]
+    fn test_repair_cuts_at_first_corrupt_record() {
+        let dir = three_segments("recover_repair");
+        let path = segment_path(&dir, 2);
+        let first = {
+            let log = MappedLog::open(&path).unwrap();
+            let mut records = log.records();
+            records.next().unwrap().unwrap();
+            records.offset()
+        };
+        corrupt(path.clone(), first + HEADER_SIZE as u64);
+        assert!(recover(&dir, &MapMemtable::default(), RecoveryPolicy::Strict).is_err());
+
+        assert_eq!(repair(&path).unwrap(), Some(first));
+        assert_eq!(fs::metadata(&path).unwrap().len(), first);
+        assert_eq!(repair(&path).unwrap(), None);
+
+        let memtable = MapMemtable::default();
+        let stats = recover(&dir, &memtable, RecoveryPolicy::Strict).unwrap();
+        assert_eq!((stats.records, stats.corrupt), (5, 0));
+        assert_eq!(memtable.get(b"c"), Some(b"3".to_vec()));
+        assert_eq!(memtable.get(b"b"), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn test_manager_carries_on_after_recovery() {
+        let dir = three_segments("recover_manager");
+
+        let mut manager = LogManager::open(&dir, LogOptions::default()).unwrap();
+        let memtable = MapMemtable::default();
+        let stats = manager.recover(&memtable, RecoveryPolicy::Strict).unwrap();
+        assert_eq!(stats.segments, 3);
+        assert_eq!(manager.last_sequence(), 6);
+
+        manager.put(b"e", b"5").unwrap();
+        assert_eq!(manager.last_sequence(), 7);
+        let stats = recover(&dir, &MapMemtable::default(), RecoveryPolicy::Strict).unwrap();
+        assert_eq!((stats.segments, stats.records), (4, 7));
+    }
+}
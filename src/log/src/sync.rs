@@ -0,0 +1,245 @@
+use std::fs::File;
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// When the log forces written records onto stable storage.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// Never, the operating system writes the data back whenever it sees fit.
+    #[default]
+    None,
+    /// Every record is synced before `put`/`delete` returns.
+    EveryWrite,
+    /// A background thread syncs the log every so many milliseconds. A crash loses at most
+    /// the records written during the last interval.
+    Interval(u64),
+    /// Writers wait for durability through a [`SyncPoint`] and everyone waiting at the same
+    /// time is covered by a single sync.
+    GroupCommit,
+}
+
+struct GroupState {
+    //Offset up to which records have been written and synced respectively
+    written: u64,
+    synced: u64,
+    //Whether some thread is syncing on behalf of everyone else right now
+    syncing: bool,
+    syncs: u64,
+}
+
+/// Shares syncs of one log between every writer waiting on it.
+pub(crate) struct GroupCommit {
+    file: File,
+    state: Mutex<GroupState>,
+    synced: Condvar,
+}
+
+impl GroupCommit {
+    pub(crate) fn new(file: File, offset: u64) -> GroupCommit {
+        GroupCommit {
+            file,
+            state: Mutex::new(GroupState {
+                written: offset,
+                synced: offset,
+                syncing: false,
+                syncs: 0,
+            }),
+            synced: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn written(&self, offset: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.written = state.written.max(offset);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn sync_count(&self) -> u64 {
+        self.state.lock().unwrap().syncs
+    }
+
+    // Blocks until everything up to `offset` is synced. The first thread to get here while
+    // no sync is in progress becomes the leader and syncs everything written so far, the
+    // others wait for it and only sync themselves if that was not enough.
+    fn wait(&self, offset: u64) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.synced >= offset {
+                return Ok(());
+            }
+            if state.syncing {
+                state = self.synced.wait(state).unwrap();
+                continue;
+            }
+
+            state.syncing = true;
+            let target = state.written;
+            drop(state);
+
+            let result = self.file.sync_data();
+
+            state = self.state.lock().unwrap();
+            state.syncing = false;
+            if result.is_ok() {
+                state.synced = state.synced.max(target);
+                state.syncs += 1;
+            }
+            self.synced.notify_all();
+            result?;
+        }
+    }
+}
+
+/// Everything written to a log up to some point. Waiting on it returns once all of that is
+/// on stable storage, as far as the [`SyncMode`] of the log is concerned.
+///
+/// In [`SyncMode::GroupCommit`] writers are expected to release whatever lock guards the
+/// `LogWriter` before waiting, so that other writers can add their records to the same sync:
+///
+/// ```no_run
+/// # use log::{LogWriter, SyncMode};
+/// # use std::sync::Mutex;
+/// let writer = Mutex::new(LogWriter::with_sync_mode("00001.log", SyncMode::GroupCommit));
+/// let point = {
+///     let mut writer = writer.lock().unwrap();
+///     writer.put(b"foo", b"bar").unwrap();
+///     writer.sync_point()
+/// };
+/// point.wait().unwrap();
+/// ```
+#[must_use]
+pub struct SyncPoint {
+    pub(crate) group: Option<Arc<GroupCommit>>,
+    pub(crate) offset: u64,
+}
+
+impl SyncPoint {
+    pub fn wait(self) -> io::Result<()> {
+        match self.group {
+            Some(group) => group.wait(self.offset),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Background thread behind [`SyncMode::Interval`]. It syncs one final time when stopped.
+pub(crate) struct IntervalSync {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+    //First error hit by the background thread, handed to the next writer
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl IntervalSync {
+    pub(crate) fn start(file: File, interval: Duration) -> IntervalSync {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let error = Arc::new(Mutex::new(None));
+        let thread_error = Arc::clone(&error);
+
+        let handle = thread::Builder::new()
+            .name("log-sync".into())
+            .spawn(move || loop {
+                //Either asked to stop or the writer is gone, sync one last time on the way out
+                let stop = !matches!(
+                    stopped.recv_timeout(interval),
+                    Err(RecvTimeoutError::Timeout)
+                );
+                if let Err(err) = file.sync_data() {
+                    thread_error.lock().unwrap().get_or_insert(err);
+                }
+                if stop {
+                    break;
+                }
+            })
+            .expect("Failed to spawn log sync thread");
+
+        IntervalSync {
+            stop: Some(stop),
+            handle: Some(handle),
+            error,
+        }
+    }
+
+    pub(crate) fn take_error(&self) -> io::Result<()> {
+        match self.error.lock().unwrap().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    // Makes it look as if the background thread failed to sync.
+    #[cfg(test)]
+    pub(crate) fn fail(&self, err: io::Error) {
+        self.error.lock().unwrap().get_or_insert(err);
+    }
+}
+
+impl Drop for IntervalSync {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::log_path;
+    use std::thread;
+
+    fn group(name: &str) -> GroupCommit {
+        GroupCommit::new(File::create(log_path(name)).unwrap(), 0)
+    }
+
+    #[test]
+    fn test_one_sync_covers_every_waiting_write() {
+        let group = Arc::new(group("group_commit_single"));
+        group.written(10);
+        group.written(20);
+        group.written(30);
+
+        let point = |offset| SyncPoint {
+            group: Some(Arc::clone(&group)),
+            offset,
+        };
+        point(30).wait().unwrap();
+        point(10).wait().unwrap();
+        point(20).wait().unwrap();
+
+        assert_eq!(group.sync_count(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_waiters_share_syncs() {
+        let group = Arc::new(group("group_commit_concurrent"));
+        let offset = Mutex::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        let point = {
+                            let mut offset = offset.lock().unwrap();
+                            *offset += 1;
+                            group.written(*offset);
+                            SyncPoint {
+                                group: Some(Arc::clone(&group)),
+                                offset: *offset,
+                            }
+                        };
+                        point.wait().unwrap();
+                    }
+                });
+            }
+        });
+
+        let syncs = group.sync_count();
+        assert!((1..=400).contains(&syncs));
+        assert_eq!(group.state.lock().unwrap().synced, 400);
+    }
+}
@@ -0,0 +1,107 @@
+//Helpers shared by the tests of several modules
+
+use crate::SequenceNumber;
+use memtable::{Entry, InternalKey, Lookup, Memtable, ValueType, MAX_SEQUENCE};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+use std::path::PathBuf;
+
+/// Path of a log file in the temp directory, unique to `name` and the test process. Every test
+/// gets its own file so that tests running in parallel do not clobber each other.
+pub(crate) fn log_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("nosql-{}-{}.log", name, std::process::id()))
+}
+
+/// Empty directory in the temp directory for the segments of a log, unique to `name` and the
+/// test process.
+pub(crate) fn log_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nosql-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Memtable over a `BTreeMap`, so that log tests do not depend on the memtable implementations.
+#[derive(Default)]
+pub(crate) struct MapMemtable {
+    //Values of deletions are empty
+    map: RefCell<BTreeMap<InternalKey, Vec<u8>>>,
+}
+
+impl MapMemtable {
+    fn write(&self, key: InternalKey, val: &[u8]) {
+        self.map
+            .borrow_mut()
+            .entry(key)
+            .or_insert_with(|| val.to_vec());
+    }
+}
+
+impl Memtable for MapMemtable {
+    type Iter<'a> = std::vec::IntoIter<Entry>;
+
+    fn lookup(&self, key: &[u8], snapshot: SequenceNumber) -> Lookup {
+        let map = self.map.borrow();
+        let newest = map.range(InternalKey::lookup(key, snapshot)..).next();
+        match newest {
+            Some((found, _)) if found.user_key() != key => Lookup::NotFound,
+            Some((found, val)) if found.value_type() == ValueType::Value => {
+                Lookup::Found(val.clone())
+            }
+            Some(_) => Lookup::Deleted,
+            None => Lookup::NotFound,
+        }
+    }
+
+    fn put(&self, key: &[u8], val: &[u8], seq: SequenceNumber) {
+        self.write(InternalKey::new(key, seq, ValueType::Value), val);
+    }
+
+    fn delete(&self, key: &[u8], seq: SequenceNumber) {
+        self.write(InternalKey::new(key, seq, ValueType::Deletion), &[]);
+    }
+
+    fn len(&self) -> usize {
+        self.map.borrow().len()
+    }
+
+    fn approximate_memory_usage(&self) -> usize {
+        self.map
+            .borrow()
+            .iter()
+            .map(|(key, val)| key.user_key().len() + val.len())
+            .sum()
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.range(..)
+    }
+
+    fn range<'k, R: RangeBounds<&'k [u8]>>(&self, range: R) -> Self::Iter<'_> {
+        //Every version of the keys at either end
+        let start = match range.start_bound() {
+            Bound::Included(start) => Bound::Included(InternalKey::lookup(start, MAX_SEQUENCE)),
+            Bound::Excluded(start) => Bound::Excluded(InternalKey::last(start)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => Bound::Included(InternalKey::last(end)),
+            Bound::Excluded(end) => Bound::Excluded(InternalKey::lookup(end, MAX_SEQUENCE)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let entries: Vec<Entry> = self
+            .map
+            .borrow()
+            .range((start, end))
+            .map(|(key, val)| Entry {
+                key: key.user_key().to_vec(),
+                sequence: key.sequence(),
+                value: match key.value_type() {
+                    ValueType::Value => Some(val.clone()),
+                    ValueType::Deletion => None,
+                },
+            })
+            .collect();
+        entries.into_iter()
+    }
+}
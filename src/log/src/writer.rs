@@ -0,0 +1,287 @@
+use crate::batch::WriteBatch;
+use crate::compression::Compression;
+use crate::crc32c;
+use crate::format::{self, FragmentType, BLOCK_SIZE, HEADER_SIZE};
+use crate::sync::{GroupCommit, IntervalSync, SyncMode, SyncPoint};
+use crate::{LogError, LogReader};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct LogWriter {
+    writer: File,
+    //Number of bytes already used in the current block
+    block_offset: usize,
+    //Number of bytes written to the file
+    offset: u64,
+    last_sequence: u64,
+    sync_mode: SyncMode,
+    compression: Compression,
+    group_commit: Option<Arc<GroupCommit>>,
+    interval_sync: Option<IntervalSync>,
+}
+
+impl LogWriter {
+    /// Creates the log at `path`, truncating whatever was there. Use [`LogWriter::open_append`]
+    /// to keep an existing log and [`LogWriter::create_new`] to refuse to overwrite one.
+    pub fn new<P: AsRef<Path>>(path: P) -> LogWriter {
+        Self::with_sync_mode(path, SyncMode::None)
+    }
+
+    pub fn with_sync_mode<P: AsRef<Path>>(path: P, sync_mode: SyncMode) -> LogWriter {
+        let mut writer = Self::from_file(File::create(path).unwrap(), 0, 0);
+        writer.set_sync_mode(sync_mode);
+        writer
+    }
+
+    /// Creates the log at `path`, failing with [`io::ErrorKind::AlreadyExists`] if there is
+    /// something there already.
+    pub fn create_new<P: AsRef<Path>>(path: P) -> io::Result<LogWriter> {
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        Ok(Self::from_file(file, 0, 0))
+    }
+
+    /// Opens the log at `path` to write more records after the ones already in it, creating it
+    /// if it does not exist. Sequence numbers carry on after the last record found.
+    ///
+    /// Whatever follows the last intact record is a write that was torn by a crash and is cut
+    /// off, so that new records start on a clean fragment boundary. Corrupt records with
+    /// intact ones after them are left alone for the reader to skip.
+    pub fn open_append<P: AsRef<Path>>(path: P) -> io::Result<LogWriter> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        //End of the last intact record
+        let mut end = 0;
+        let mut last_sequence = 0;
+        let mut reader = LogReader::new(path);
+        while let Some(record) = reader.next() {
+            match record {
+                Ok(record) => {
+                    end = reader.offset();
+                    last_sequence = last_sequence.max(record.sequence());
+                }
+                Err(LogError::Io(err)) => return Err(err),
+                Err(_) => {}
+            }
+        }
+
+        if file.metadata()?.len() > end {
+            file.set_len(end)?;
+            file.sync_data()?;
+        }
+
+        Ok(Self::from_file(file, end, last_sequence))
+    }
+
+    fn from_file(writer: File, offset: u64, last_sequence: u64) -> LogWriter {
+        LogWriter {
+            writer,
+            block_offset: (offset % BLOCK_SIZE as u64) as usize,
+            offset,
+            last_sequence,
+            sync_mode: SyncMode::None,
+            compression: Compression::None,
+            group_commit: None,
+            interval_sync: None,
+        }
+    }
+
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        //Stopping an interval sync thread makes it sync one last time
+        self.interval_sync = None;
+        self.group_commit = None;
+
+        match sync_mode {
+            SyncMode::GroupCommit => {
+                self.group_commit = Some(Arc::new(GroupCommit::new(
+                    self.writer.try_clone().unwrap(),
+                    self.offset,
+                )));
+            }
+            SyncMode::Interval(millis) => {
+                self.interval_sync = Some(IntervalSync::start(
+                    self.writer.try_clone().unwrap(),
+                    Duration::from_millis(millis),
+                ));
+            }
+            SyncMode::None | SyncMode::EveryWrite => {}
+        }
+        self.sync_mode = sync_mode;
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    /// Applies to records written from now on, the reader handles any mix of compressed and
+    /// uncompressed records.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Everything written so far. Only [`SyncMode::GroupCommit`] actually waits on it, in every
+    /// other mode the records are as durable as they are going to get once `put` returns.
+    pub fn sync_point(&self) -> SyncPoint {
+        SyncPoint {
+            group: self.group_commit.clone(),
+            offset: self.offset,
+        }
+    }
+
+    /// Forces everything written so far onto stable storage, whatever the sync mode.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.writer.sync_data()
+    }
+
+    /// Appends a write of `val` to `key` and returns the number of bytes it took in the log,
+    /// including fragment headers and any block padding in front of it.
+    pub fn put(&mut self, key: &[u8], val: &[u8]) -> io::Result<usize> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, val);
+        self.write(&mut batch)
+    }
+
+    /// Appends a delete of `key`, see [`LogWriter::put`] for the return value.
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<usize> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(&mut batch)
+    }
+
+    pub fn put_str(&mut self, key: &str, val: &str) -> io::Result<usize> {
+        self.put(key.as_bytes(), val.as_bytes())
+    }
+
+    pub fn delete_str(&mut self, key: &str) -> io::Result<usize> {
+        self.delete(key.as_bytes())
+    }
+
+    /// Appends every operation in `batch` as one record, see [`LogWriter::put`] for the return
+    /// value. The operations are given the next consecutive sequence numbers, which are stored
+    /// in `batch` so that it can be applied to a memtable afterwards. An empty batch is not
+    /// logged at all.
+    pub fn write(&mut self, batch: &mut WriteBatch) -> io::Result<usize> {
+        if batch.is_empty() {
+            return Ok(0);
+        }
+
+        batch.set_sequence(self.last_sequence + 1);
+        let record = format::compress_record(format::encode_batch(batch), self.compression);
+        let size = self.add_records(&[&record])?;
+        self.last_sequence += batch.len() as u64;
+
+        Ok(size)
+    }
+
+    /// Sequence number of the last operation written, 0 if nothing has been written yet.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    // Makes it look as if the background thread of `SyncMode::Interval` failed to sync.
+    #[cfg(test)]
+    pub(crate) fn fail_interval_sync(&self, err: io::Error) {
+        self.interval_sync.as_ref().unwrap().fail(err);
+    }
+
+    // For a writer that continues a log started in another file.
+    pub(crate) fn set_last_sequence(&mut self, sequence: u64) {
+        self.last_sequence = sequence;
+    }
+
+    /// Number of bytes written to the file so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    // Writes every record in one go and syncs once for all of them, as far as the sync mode
+    // asks for it.
+    pub(crate) fn add_records(&mut self, records: &[&[u8]]) -> io::Result<usize> {
+        let size = records
+            .iter()
+            .map(|record| record.len() + HEADER_SIZE)
+            .sum();
+        if let Some(interval_sync) = &self.interval_sync {
+            interval_sync.take_error()?;
+        }
+
+        //Only moved on once the records are in the file, a failed write must not shift the
+        //block boundaries the next records are split along
+        let mut block_offset = self.block_offset;
+        let mut buffer: Vec<u8> = Vec::with_capacity(size);
+        for record in records {
+            Self::fragment(&mut buffer, &mut block_offset, record);
+        }
+
+        self.writer.write_all(&buffer)?;
+        self.block_offset = block_offset;
+        self.offset += buffer.len() as u64;
+
+        match self.sync_mode {
+            SyncMode::EveryWrite => self.writer.sync_data()?,
+            SyncMode::GroupCommit => {
+                if let Some(group_commit) = &self.group_commit {
+                    group_commit.written(self.offset);
+                }
+            }
+            SyncMode::None | SyncMode::Interval(_) => {}
+        }
+
+        Ok(buffer.len())
+    }
+
+    // Splits `record` into fragments along block boundaries and appends them to `buffer`.
+    // `block_offset` is the number of bytes used in the block the record starts in, and is
+    // moved past the record.
+    fn fragment(buffer: &mut Vec<u8>, block_offset: &mut usize, record: &[u8]) {
+        let mut left = record;
+        let mut begin = true;
+
+        loop {
+            let leftover = BLOCK_SIZE - *block_offset;
+            if leftover < HEADER_SIZE {
+                //Not even a header fits, so the rest of the block becomes padding
+                buffer.resize(buffer.len() + leftover, 0);
+                *block_offset = 0;
+            }
+
+            let available = BLOCK_SIZE - *block_offset - HEADER_SIZE;
+            let fragment_length = left.len().min(available);
+            let end = fragment_length == left.len();
+
+            let fragment_type = match (begin, end) {
+                (true, true) => FragmentType::Full,
+                (true, false) => FragmentType::First,
+                (false, true) => FragmentType::Last,
+                (false, false) => FragmentType::Middle,
+            };
+
+            Self::emit_fragment(buffer, fragment_type, &left[..fragment_length]);
+            *block_offset += HEADER_SIZE + fragment_length;
+
+            left = &left[fragment_length..];
+            begin = false;
+            if end {
+                break;
+            }
+        }
+    }
+
+    fn emit_fragment(buffer: &mut Vec<u8>, fragment_type: FragmentType, data: &[u8]) {
+        debug_assert!(data.len() <= BLOCK_SIZE - HEADER_SIZE);
+
+        let checksum = crc32c::extend(crc32c::checksum(&[fragment_type as u8]), data);
+        buffer.extend_from_slice(&checksum.to_be_bytes());
+        buffer.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        buffer.push(fragment_type as u8);
+        buffer.extend_from_slice(data);
+    }
+}
@@ -0,0 +1,257 @@
+use std::alloc::{self, Layout};
+use std::borrow::Borrow;
+use std::cmp::Ordering as CmpOrdering;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHUNK_ALIGN: usize = 16;
+//Anything larger gets a chunk of its own, so that little of a chunk is left unused
+const MAX_SHARED_ALLOCATION: usize = CHUNK_SIZE / 4;
+
+struct Chunk {
+    data: *mut u8,
+    layout: Layout,
+    used: AtomicUsize,
+    //Next chunk in the list of every chunk of the arena
+    next: *mut Chunk,
+}
+
+impl Chunk {
+    fn new(size: usize, align: usize) -> *mut Chunk {
+        let layout = Layout::from_size_align(size.max(1), align).unwrap();
+        let data = unsafe { alloc::alloc(layout) };
+        if data.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Box::into_raw(Box::new(Chunk {
+            data,
+            layout,
+            used: AtomicUsize::new(0),
+            next: ptr::null_mut(),
+        }))
+    }
+
+    fn free(chunk: *mut Chunk) {
+        let chunk = unsafe { Box::from_raw(chunk) };
+        unsafe { alloc::dealloc(chunk.data, chunk.layout) }
+    }
+
+    // Returns the allocation, or `None` if the chunk is too full.
+    fn bump(&self, layout: Layout) -> Option<*mut u8> {
+        let mut used = self.used.load(Ordering::Relaxed);
+        loop {
+            let address = self.data as usize + used;
+            let start = used + (address.next_multiple_of(layout.align()) - address);
+            let end = start + layout.size();
+            if end > self.layout.size() {
+                return None;
+            }
+            match self
+                .used
+                .compare_exchange_weak(used, end, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return Some(unsafe { self.data.add(start) }),
+                Err(current) => used = current,
+            }
+        }
+    }
+}
+
+/// Bump allocator that any number of threads can allocate from at once without locking.
+/// Nothing allocated from it is freed before the arena itself, nor is it dropped: whoever puts
+/// something that needs dropping into it has to drop it in place.
+pub struct Arena {
+    //Chunk allocations are bumped from
+    current: AtomicPtr<Chunk>,
+    //Every chunk, to free them with the arena
+    chunks: AtomicPtr<Chunk>,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena {
+            current: AtomicPtr::new(ptr::null_mut()),
+            chunks: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn allocate(&self, layout: Layout) -> *mut u8 {
+        if layout.size() > MAX_SHARED_ALLOCATION || layout.align() > CHUNK_ALIGN {
+            let chunk = Chunk::new(layout.size(), layout.align().max(CHUNK_ALIGN));
+            self.push(chunk);
+            return unsafe { (*chunk).data };
+        }
+
+        loop {
+            let chunk = self.current.load(Ordering::Acquire);
+            if !chunk.is_null() {
+                if let Some(data) = unsafe { (*chunk).bump(layout) } {
+                    return data;
+                }
+            }
+            let new_chunk = Chunk::new(CHUNK_SIZE, CHUNK_ALIGN);
+            match self.current.compare_exchange(
+                chunk,
+                new_chunk,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => self.push(new_chunk),
+                //Someone else replaced the chunk first, allocate from theirs
+                Err(_) => Chunk::free(new_chunk),
+            }
+        }
+    }
+
+    /// Moves `value` into the arena.
+    pub fn alloc<T>(&self, value: T) -> *mut T {
+        let data = self.allocate(Layout::new::<T>()) as *mut T;
+        unsafe { data.write(value) };
+        data
+    }
+
+    fn push(&self, chunk: *mut Chunk) {
+        let mut head = self.chunks.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*chunk).next = head };
+            match self.chunks.compare_exchange_weak(
+                head,
+                chunk,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        let mut chunk = *self.chunks.get_mut();
+        while !chunk.is_null() {
+            let next = unsafe { (*chunk).next };
+            Chunk::free(chunk);
+            chunk = next;
+        }
+    }
+}
+
+/// Bytes copied into an [`Arena`]. Only valid for as long as the arena is, which whoever
+/// holds on to them has to make sure of.
+#[derive(Clone, Copy)]
+pub struct ArenaBytes {
+    data: *const u8,
+    len: usize,
+}
+
+impl ArenaBytes {
+    // Bytes at `data` that were copied into an arena by hand. They must not be written to
+    // afterwards.
+    pub(crate) unsafe fn from_raw_parts(data: *const u8, len: usize) -> ArenaBytes {
+        ArenaBytes { data, len }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+//The bytes are never written to after they were copied into the arena
+unsafe impl Send for ArenaBytes {}
+unsafe impl Sync for ArenaBytes {}
+
+impl Borrow<[u8]> for ArenaBytes {
+    fn borrow(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl PartialEq for ArenaBytes {
+    fn eq(&self, other: &ArenaBytes) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for ArenaBytes {}
+
+impl PartialOrd for ArenaBytes {
+    fn partial_cmp(&self, other: &ArenaBytes) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ArenaBytes {
+    fn cmp(&self, other: &ArenaBytes) -> CmpOrdering {
+        self.as_slice().cmp(other.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn copy_bytes(arena: &Arena, bytes: &[u8]) -> ArenaBytes {
+        let data = arena.allocate(Layout::for_value(bytes));
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), data, bytes.len());
+            ArenaBytes::from_raw_parts(data, bytes.len())
+        }
+    }
+
+    #[test]
+    fn test_allocations_are_aligned() {
+        let arena = Arena::new();
+        let byte = arena.alloc(1u8);
+        let word = arena.alloc(2u64);
+        assert_eq!(word as usize % 8, 0);
+        assert_eq!(unsafe { (*byte, *word) }, (1, 2));
+        //The byte, then the padding after it
+        assert_eq!(word as usize - byte as usize, 8);
+
+        let bytes = copy_bytes(&arena, b"hello");
+        assert_eq!(bytes.as_slice(), b"hello");
+        assert_eq!(bytes.as_slice().as_ptr() as usize - word as usize, 8);
+        assert_eq!(copy_bytes(&arena, b"").as_slice(), b"");
+    }
+
+    #[test]
+    fn test_large_and_many_allocations() {
+        let arena = Arena::new();
+        let large = vec![7; CHUNK_SIZE * 2];
+        assert_eq!(copy_bytes(&arena, &large).as_slice(), &large[..]);
+
+        //Spills over into new chunks
+        let all: Vec<ArenaBytes> = (0..10_000u32)
+            .map(|i| copy_bytes(&arena, &i.to_be_bytes()))
+            .collect();
+        for (i, bytes) in all.iter().enumerate() {
+            assert_eq!(bytes.as_slice(), (i as u32).to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn test_parallel_allocations_do_not_overlap() {
+        let arena = Arena::new();
+        let all: Vec<Vec<ArenaBytes>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4u8)
+                .map(|thread| {
+                    let arena = &arena;
+                    s.spawn(move || {
+                        (0..5000)
+                            .map(|_| copy_bytes(arena, &[thread; 24]))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for (thread, bytes) in all.iter().enumerate() {
+            assert!(bytes.iter().all(|b| b.as_slice() == [thread as u8; 24]));
+        }
+    }
+}
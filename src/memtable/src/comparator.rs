@@ -0,0 +1,25 @@
+use std::cmp::Ordering;
+
+/// Order of the keys of a [`crate::SkipList`]. Has to be a total order, and lookups by a
+/// borrowed form of the key have to order the borrowed forms the same way.
+///
+/// Closures taking two keys implement it as well.
+pub trait Comparator<T: ?Sized> {
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+/// Orders keys by their [`Ord`] implementation.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Natural;
+
+impl<T: Ord + ?Sized> Comparator<T> for Natural {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+impl<T: ?Sized, F: Fn(&T, &T) -> Ordering> Comparator<T> for F {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
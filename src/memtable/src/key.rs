@@ -0,0 +1,216 @@
+use crate::arena::ArenaBytes;
+use crate::comparator::Comparator;
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+/// Position of a write in the total order of all writes, assigned when the write is logged.
+pub type SequenceNumber = u64;
+
+/// Largest sequence number, looking a key up with it sees every write.
+pub const MAX_SEQUENCE: SequenceNumber = u64::MAX;
+
+//Bytes the sequence number and the type take up after the user key of an encoded key
+const TRAILER_SIZE: usize = 9;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ValueType {
+    Deletion = 0,
+    Value = 1,
+}
+
+impl ValueType {
+    fn from_u8(value: u8) -> ValueType {
+        if value == ValueType::Value as u8 {
+            ValueType::Value
+        } else {
+            ValueType::Deletion
+        }
+    }
+}
+
+/// Key a memtable stores its entries under. Several versions of the same user key can live
+/// side by side, they are told apart by the sequence number of the write that made them.
+///
+/// Keys are ordered by user key, then newest first, so the first entry at or after
+/// [`InternalKey::lookup`] is the latest version visible at that sequence number.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InternalKey {
+    user_key: Vec<u8>,
+    sequence: SequenceNumber,
+    value_type: ValueType,
+}
+
+impl InternalKey {
+    pub fn new(user_key: &[u8], sequence: SequenceNumber, value_type: ValueType) -> InternalKey {
+        InternalKey {
+            user_key: user_key.to_vec(),
+            sequence,
+            value_type,
+        }
+    }
+
+    /// Key that sorts before every version of `user_key` written at or before `sequence`.
+    pub fn lookup(user_key: &[u8], sequence: SequenceNumber) -> InternalKey {
+        //Values sort before deletions with the same sequence number
+        Self::new(user_key, sequence, ValueType::Value)
+    }
+
+    /// Key that sorts after every version of `user_key`.
+    pub fn last(user_key: &[u8]) -> InternalKey {
+        Self::new(user_key, 0, ValueType::Deletion)
+    }
+
+    /// The user key followed by the sequence number and the type. [`InternalKeyOrder`] orders
+    /// encoded keys the same way as the keys themselves.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(self.user_key.len() + TRAILER_SIZE);
+        encoded.extend_from_slice(&self.user_key);
+        encoded.extend_from_slice(&self.sequence.to_be_bytes());
+        encoded.push(self.value_type as u8);
+        encoded
+    }
+
+    pub fn user_key(&self) -> &[u8] {
+        &self.user_key
+    }
+
+    pub fn sequence(&self) -> SequenceNumber {
+        self.sequence
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+}
+
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.user_key
+            .cmp(&other.user_key)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+            .then_with(|| other.value_type.cmp(&self.value_type))
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// User key, sequence number and type of a key encoded with `InternalKey::encode`.
+pub(crate) fn decode(encoded: &[u8]) -> (&[u8], SequenceNumber, ValueType) {
+    let (user_key, trailer) = encoded.split_at(encoded.len() - TRAILER_SIZE);
+    let sequence = SequenceNumber::from_be_bytes(trailer[..8].try_into().unwrap());
+    (user_key, sequence, ValueType::from_u8(trailer[8]))
+}
+
+/// Orders keys encoded with [`InternalKey::encode`] like [`InternalKey`] orders the keys.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct InternalKeyOrder;
+
+impl Comparator<[u8]> for InternalKeyOrder {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        let (a_user_key, a_trailer) = a.split_at(a.len() - TRAILER_SIZE);
+        let (b_user_key, b_trailer) = b.split_at(b.len() - TRAILER_SIZE);
+        //Sequence numbers are big endian, so newer trailers compare greater
+        a_user_key
+            .cmp(b_user_key)
+            .then_with(|| b_trailer.cmp(a_trailer))
+    }
+}
+
+impl Comparator<ArenaBytes> for InternalKeyOrder {
+    fn compare(&self, a: &ArenaBytes, b: &ArenaBytes) -> Ordering {
+        self.compare(a.as_slice(), b.as_slice())
+    }
+}
+
+// Bounds on internal keys that take in every version of the user keys between `start` and
+// `end`.
+pub(crate) fn version_bounds(
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+) -> (Bound<InternalKey>, Bound<InternalKey>) {
+    let start = match start {
+        Bound::Included(start) => Bound::Included(InternalKey::lookup(start, MAX_SEQUENCE)),
+        Bound::Excluded(start) => Bound::Excluded(InternalKey::last(start)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let end = match end {
+        Bound::Included(end) => Bound::Included(InternalKey::last(end)),
+        Bound::Excluded(end) => Bound::Excluded(InternalKey::lookup(end, MAX_SEQUENCE)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orders_by_user_key_then_newest_first() {
+        let mut keys = [
+            InternalKey::new(b"b", 1, ValueType::Value),
+            InternalKey::new(b"a", 1, ValueType::Value),
+            InternalKey::new(b"a", 3, ValueType::Deletion),
+            InternalKey::new(b"a", 2, ValueType::Value),
+            InternalKey::new(&[0xFF], 0, ValueType::Value),
+        ];
+        keys.sort();
+
+        let order: Vec<(&[u8], SequenceNumber)> =
+            keys.iter().map(|k| (k.user_key(), k.sequence())).collect();
+        assert_eq!(
+            order,
+            [
+                (&b"a"[..], 3),
+                (&b"a"[..], 2),
+                (&b"a"[..], 1),
+                (&b"b"[..], 1),
+                (&[0xFF][..], 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_encoded_keys_keep_their_order() {
+        let keys = [
+            InternalKey::new(b"", 7, ValueType::Value),
+            InternalKey::new(b"a", u64::MAX, ValueType::Value),
+            InternalKey::new(b"a", 256, ValueType::Deletion),
+            InternalKey::new(b"a", 255, ValueType::Value),
+            InternalKey::new(b"a", 255, ValueType::Deletion),
+            InternalKey::new(b"a\x00", 1, ValueType::Value),
+            InternalKey::last(b"b"),
+        ];
+        for pair in keys.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert_eq!(
+                InternalKeyOrder.compare(&pair[0].encode()[..], &pair[1].encode()[..]),
+                Ordering::Less
+            );
+        }
+        for key in &keys {
+            let encoded = key.encode();
+            assert_eq!(
+                decode(&encoded),
+                (key.user_key(), key.sequence(), key.value_type())
+            );
+        }
+    }
+
+    #[test]
+    fn test_lookup_key_sorts_before_visible_versions() {
+        let lookup = InternalKey::lookup(b"a", 2);
+
+        assert!(lookup > InternalKey::new(b"a", 3, ValueType::Value));
+        assert!(lookup <= InternalKey::new(b"a", 2, ValueType::Value));
+        assert!(lookup < InternalKey::new(b"a", 2, ValueType::Deletion));
+        assert!(lookup < InternalKey::new(b"a", 1, ValueType::Value));
+        assert!(
+            InternalKey::lookup(b"a", MAX_SEQUENCE) < InternalKey::new(b"a", 0, ValueType::Value)
+        );
+    }
+}
@@ -1,10 +1,79 @@
+mod arena;
+mod comparator;
+mod key;
+mod memory_management;
 mod rbtree;
 mod skiplist;
 mod util;
-mod memory_management;
 
-trait Memtable {
-    fn get(&self, key: &str) -> Option<String>;
-    fn put(&mut self, key: &str, val: &str);
-    fn delete(&mut self, key: &str);
+pub use comparator::{Comparator, Natural};
+pub use key::{InternalKey, SequenceNumber, ValueType, MAX_SEQUENCE};
+pub use rbtree::RBTree;
+pub use skiplist::{Guard, SkipList, SkipListMemtable};
+
+use std::ops::RangeBounds;
+
+/// What a memtable knows about a key as of a sequence number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    Found(Vec<u8>),
+    /// The latest write to the key deleted it. Older data, such as that of tables flushed
+    /// earlier, must not be looked at.
+    Deleted,
+    NotFound,
+}
+
+/// Write to a key, as handed out when iterating over a memtable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: Vec<u8>,
+    pub sequence: SequenceNumber,
+    /// `None` if the write was a deletion.
+    pub value: Option<Vec<u8>>,
+}
+
+/// Keys and values are arbitrary bytes. Every write carries the sequence number it was logged
+/// with and is kept as an entry of its own under an [`InternalKey`], so older versions of a
+/// key stay around for snapshots. Writes applied out of order, as concurrent writers do, end
+/// up with the same contents as writes applied in order. Writing an internal key that is
+/// there already, as replaying a log twice does, leaves the entry as it is.
+///
+/// Writes take `&self` so implementations that support it can be written to from several
+/// threads at once.
+pub trait Memtable {
+    type Iter<'a>: Iterator<Item = Entry>
+    where
+        Self: 'a;
+
+    /// What the latest write to `key` with a sequence number of at most `snapshot` did, pass
+    /// [`MAX_SEQUENCE`] to see every write.
+    fn lookup(&self, key: &[u8], snapshot: SequenceNumber) -> Lookup;
+
+    /// Latest value of `key`, `None` if it was never written or the latest write deleted it.
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.lookup(key, MAX_SEQUENCE) {
+            Lookup::Found(val) => Some(val),
+            Lookup::Deleted | Lookup::NotFound => None,
+        }
+    }
+
+    fn put(&self, key: &[u8], val: &[u8], seq: SequenceNumber);
+
+    fn delete(&self, key: &[u8], seq: SequenceNumber);
+
+    /// Number of entries, every version of every key and deletions included.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes taken up by the memtable, used to decide when to flush it.
+    fn approximate_memory_usage(&self) -> usize;
+
+    /// Entries in key order, the versions of a key newest first, deletions included.
+    fn iter(&self) -> Self::Iter<'_>;
+
+    /// Entries with a key inside `range` in the order of [`Memtable::iter`].
+    fn range<'k, R: RangeBounds<&'k [u8]>>(&self, range: R) -> Self::Iter<'_>;
 }
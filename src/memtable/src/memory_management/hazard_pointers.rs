@@ -14,8 +14,6 @@ pub struct HazarPointerRecord<T> {
     pub active: AtomicBool,
     pub r_list: HashSet<*mut T>,
     pub r_count: usize,
-    pub head: Arc<AtomicPtr<HazarPointerRecord<T>>>,
-    pub max_hp_count: Arc<AtomicU32>,
 }
 
 impl<T> HazarPointerRecord<T> {
@@ -26,7 +24,7 @@ impl<T> HazarPointerRecord<T> {
     ) -> *mut HazarPointerRecord<T> {
         let mut hp_record: *mut HazarPointerRecord<T>;
         hp_record = head.load(Ordering::SeqCst);
-        while hp_record != std::ptr::null_mut() {
+        while !hp_record.is_null() {
             unsafe {
                 if (*hp_record).active.load(Ordering::SeqCst) {
                     hp_record = (*hp_record).next.load(Ordering::SeqCst);
@@ -45,24 +43,22 @@ impl<T> HazarPointerRecord<T> {
         }
 
         loop {
-            unsafe {
-                let old_count = max_hp_count.load(Ordering::SeqCst);
-                if max_hp_count
-                    .compare_exchange(
-                        old_count,
-                        old_count + per_record_hp_count,
-                        Ordering::SeqCst,
-                        Ordering::SeqCst,
-                    )
-                    .is_ok()
-                {
-                    break;
-                }
+            let old_count = max_hp_count.load(Ordering::SeqCst);
+            if max_hp_count
+                .compare_exchange(
+                    old_count,
+                    old_count + per_record_hp_count,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok()
+            {
+                break;
             }
         }
 
         //cursed code
-        let mut hprec = Box::into_raw(Box::new(HazarPointerRecord {
+        let hprec = Box::into_raw(Box::new(HazarPointerRecord {
             hazard_pointers: std::iter::repeat_with(|| AtomicPtr::new(std::ptr::null_mut()))
                 .take(per_record_hp_count as usize)
                 .collect(),
@@ -70,35 +66,29 @@ impl<T> HazarPointerRecord<T> {
             active: AtomicBool::new(true),
             r_list: HashSet::new(),
             r_count: 0,
-            head:head.clone(),
-            max_hp_count: max_hp_count.clone(),
         }));
 
         loop {
             unsafe {
                 let old_head = head.load(Ordering::SeqCst);
                 (*hprec).next = AtomicPtr::new(old_head);
-                if let Ok(_) =
-                    head.compare_exchange(old_head, hprec, Ordering::SeqCst, Ordering::SeqCst)
+                if head
+                    .compare_exchange(old_head, hprec, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
                 {
                     // let _ = Box::from_raw(ptr);
                     break;
                 }
             }
         }
-        unsafe { hprec }
+        hprec
     }
 
     pub fn retire_hp_record(rec_node: *mut HazarPointerRecord<T>) {
-        let hp_length;
-        let hp_list: &Vec<AtomicPtr<T>>;
-        unsafe {
-            hp_length = (*rec_node).hazard_pointers.len();
-            hp_list = (*rec_node).hazard_pointers.as_ref();
-        }
+        let hp_list: &Vec<AtomicPtr<T>> = unsafe { &(*rec_node).hazard_pointers };
         // HazarPointerRecord::scan(self.head.load(Ordering::SeqCst),rec_node);
-        for i in 0..hp_length {
-            hp_list[i].store(std::ptr::null_mut(), Ordering::SeqCst);
+        for hazard_pointer in hp_list {
+            hazard_pointer.store(std::ptr::null_mut(), Ordering::SeqCst);
         }
         unsafe {
             (*rec_node).active.store(false, Ordering::SeqCst);
@@ -109,25 +99,27 @@ impl<T> HazarPointerRecord<T> {
     // OR
     // call to retire_node implies that `node` is a reference to an object that is no
     // longer reachable from any other object or global reference.
+    //
+    // `reclaim` releases a node once no hazard pointer points to it, every node of a list
+    // of records has to be retired with the same one.
     pub fn retire_node(
         head: *mut HazarPointerRecord<T>,
         hp_record_ptr: *mut HazarPointerRecord<T>,
         node: *mut T,
         max_r_count: usize,
+        reclaim: fn(*mut T),
     ) {
         let self_r_count;
         unsafe {
-            debug_assert!((*hp_record_ptr).active.load(Ordering::SeqCst) == true);
+            debug_assert!((*hp_record_ptr).active.load(Ordering::SeqCst));
             if (*hp_record_ptr).r_list.insert(node) {
                 (*hp_record_ptr).r_count += 1;
             }
             self_r_count = (*hp_record_ptr).r_count;
         }
         if self_r_count >= max_r_count {
-            unsafe {
-                HazarPointerRecord::scan(head, hp_record_ptr);
-                HazarPointerRecord::help_scan(head, hp_record_ptr, max_r_count);
-            }
+            HazarPointerRecord::scan(head, hp_record_ptr, reclaim);
+            HazarPointerRecord::help_scan(head, hp_record_ptr, max_r_count, reclaim);
         }
     }
 
@@ -136,9 +128,10 @@ impl<T> HazarPointerRecord<T> {
         head: *mut HazarPointerRecord<T>,
         self_ptr: *mut HazarPointerRecord<T>,
         max_hptr_count: usize,
+        reclaim: fn(*mut T),
     ) {
         let mut hp_record = head;
-        while hp_record != std::ptr::null_mut() {
+        while !hp_record.is_null() {
             unsafe {
                 if (*hp_record).active.load(Ordering::SeqCst) {
                     hp_record = (*hp_record).next.load(Ordering::SeqCst);
@@ -159,7 +152,7 @@ impl<T> HazarPointerRecord<T> {
                         (*self_ptr).r_count += 1;
                     }
                     if (*self_ptr).r_count >= max_hptr_count {
-                        HazarPointerRecord::scan(head,self_ptr);
+                        HazarPointerRecord::scan(head, self_ptr, reclaim);
                     }
                 }
                 (*hp_record).active.store(false, Ordering::SeqCst);
@@ -169,10 +162,14 @@ impl<T> HazarPointerRecord<T> {
     }
 
     /// Collect and release nodes if no hazar pointers from other hazard pointer records points to it
-    fn scan(head: *mut HazarPointerRecord<T>, self_ptr: *mut HazarPointerRecord<T>) {
+    fn scan(
+        head: *mut HazarPointerRecord<T>,
+        self_ptr: *mut HazarPointerRecord<T>,
+        reclaim: fn(*mut T),
+    ) {
         let mut hazard_ptr_collection: HashSet<*mut T> = HashSet::new();
         let mut hp_record = head;
-        while hp_record != std::ptr::null_mut() {
+        while !hp_record.is_null() {
             let hp_iter = unsafe {
                 (*hp_record).hazard_pointers.iter()
             };
@@ -199,9 +196,7 @@ impl<T> HazarPointerRecord<T> {
                     }
                 }
             } else {
-                unsafe {
-                    let _ = Box::from_raw(node);
-                }
+                reclaim(node);
             }
         }
     }
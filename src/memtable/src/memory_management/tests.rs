@@ -1,5 +1,6 @@
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     
     
@@ -8,6 +9,10 @@ mod tests {
     use std::thread;
     use crate::memory_management::hazard_pointers::HazarPointerRecord;
 
+    fn free_boxed<T>(node: *mut T) {
+        drop(unsafe { Box::from_raw(node) });
+    }
+
     fn free_hp_records<T>(rec: *mut HazarPointerRecord<T>) {
         let mut ptr = rec;
         while !ptr.is_null() {
@@ -38,7 +43,7 @@ mod tests {
         let total_hp_count = Arc::new(AtomicU32::new(0));
         let head = Arc::new(AtomicPtr::new(std::ptr::null_mut()));
 
-        let hp_record: *mut HazarPointerRecord<i32> =
+        let _: *mut HazarPointerRecord<i32> =
             HazarPointerRecord::allocate_hp_record(head.clone(), total_hp_count.clone(), 5);
 
         let hp_record: *mut HazarPointerRecord<i32> =
@@ -57,7 +62,7 @@ mod tests {
         let head = Arc::new(AtomicPtr::new(std::ptr::null_mut()));
 
         let mut handles = vec![];
-        for i in 0..2 {
+        for _ in 0..2 {
             let handle = thread::spawn({
                 let cloned_total_hp_count = Arc::clone(&total_hp_count);
                 let cloned_head = Arc::clone(&head);
@@ -78,7 +83,7 @@ mod tests {
 
         let mut record = (*head).load(Ordering::SeqCst);
         let mut count = 0;
-        while record != std::ptr::null_mut() {
+        while !record.is_null() {
             count += 1;
             unsafe {
                 assert!((*record).active.load(Ordering::SeqCst));
@@ -99,8 +104,8 @@ mod tests {
         count: u32,
     ) {
         thread::scope(|s| {
-            for i in 0..count {
-                let handle = s.spawn({
+            for _ in 0..count {
+                s.spawn({
                     let cloned_total_hp_count = Arc::clone(&total_hp_count);
                     let cloned_head = Arc::clone(&head);
 
@@ -129,24 +134,22 @@ mod tests {
         let (h1, h2) = (Box::into_raw(Box::new(10)), Box::into_raw(Box::new(10)));
         unsafe {
             let hazard_record = (*head).load(Ordering::SeqCst);
-            (*hazard_record).hazard_pointers[0].store(h1,Ordering::SeqCst);
-            (*hazard_record).hazard_pointers[1].store(h2,Ordering::SeqCst);
+            (&(*hazard_record).hazard_pointers)[0].store(h1,Ordering::SeqCst);
+            (&(*hazard_record).hazard_pointers)[1].store(h2,Ordering::SeqCst);
         }
 
-        unsafe{
-            HazarPointerRecord::retire_hp_record(head.load(Ordering::SeqCst));
-        }
+        HazarPointerRecord::retire_hp_record(head.load(Ordering::SeqCst));
 
         unsafe {
             assert!(!(*(*head).load(Ordering::SeqCst))
                 .active
                 .load(Ordering::SeqCst));
             assert_eq!(
-                (*(*head).load(Ordering::SeqCst)).hazard_pointers[0].load(Ordering::SeqCst),
+                (&(*(*head).load(Ordering::SeqCst)).hazard_pointers)[0].load(Ordering::SeqCst),
                 std::ptr::null_mut()
             );
             assert_eq!(
-                (*(*head).load(Ordering::SeqCst)).hazard_pointers[1].load(Ordering::SeqCst),
+                (&(*(*head).load(Ordering::SeqCst)).hazard_pointers)[1].load(Ordering::SeqCst),
                 std::ptr::null_mut()
             );
         }
@@ -168,9 +171,7 @@ mod tests {
         create_hp_record_in_parallel(head.clone(), total_hp_count.clone(), 10);
 
         let node = Box::into_raw(Box::new(10));
-        unsafe {
-                HazarPointerRecord::retire_node(head.load(Ordering::SeqCst),(*head).load(Ordering::SeqCst),node,0);
-        }
+        HazarPointerRecord::retire_node(head.load(Ordering::SeqCst),(*head).load(Ordering::SeqCst),node,0,free_boxed);
 
         free_hp_records(head.load(Ordering::SeqCst));
     }
@@ -185,17 +186,17 @@ mod tests {
 
         let node = Box::into_raw(Box::new(10));
         let mut hp_record = head.load(Ordering::SeqCst);
-        for i in 0..4 {
+        for _ in 0..4 {
             unsafe {
                 hp_record = (*hp_record).next.load(Ordering::SeqCst);
             }
         }
 
         unsafe {
-            (*hp_record).hazard_pointers[0].store(node,Ordering::SeqCst);
+            (&(*hp_record).hazard_pointers)[0].store(node,Ordering::SeqCst);
         }
         unsafe {
-                HazarPointerRecord::retire_node(head.load(Ordering::SeqCst),hp_record, node, 0);
+                HazarPointerRecord::retire_node(head.load(Ordering::SeqCst),hp_record, node, 0, free_boxed);
             drop(Box::from_raw(node));
         }
 
@@ -2,75 +2,30 @@
 mod tests {
     use super::*;
     use rand::distributions::{Alphanumeric, DistString};
-    
-
-    #[test]
-    fn test_node_color_update() {
-        let mut node = Node::new(
-            String::from("a"),
-            Some("askjhkjh"),
-            Color::Red,
-            None,
-            None,
-            None,
-        );
-        node.update_color(Color::Black);
-        assert_eq!(Color::Black, node.get_color());
-    }
-
-    #[test]
-    fn test_get_left_child() {
-        let node_1 = Node::new(
-            String::from("a"),
-            Some("ausdhiank"),
-            Color::Red,
-            None,
-            None,
-            None,
-        );
-        let node_1_rc = Rc::new(RefCell::new(node_1));
-        let node_2 = Node::new(
-            String::from("a"),
-            Some("asbjks"),
-            Color::Red,
-            Some(Rc::clone(&node_1_rc)),
-            None,
-            None,
-        );
-
-        assert!(Rc::ptr_eq(&node_1_rc, &node_2.get_left_child().unwrap()))
+    use rand::seq::SliceRandom;
+    use std::thread;
+
+    // Key and color of the node `path` leads to from the root, `l` going left and `r` right.
+    fn node_at(tree: &RBTree, path: &str) -> (Vec<u8>, Color) {
+        let tree = tree.tree.read().unwrap();
+        let mut index = tree.root;
+        for step in path.chars() {
+            index = if step == 'l' {
+                tree.left(index)
+            } else {
+                tree.right(index)
+            };
+        }
+        let node = tree.node(index);
+        (node.key.user_key().to_vec(), node.color)
     }
 
-    #[test]
-    fn test_get_right_child() {
-        let node_1 = Node::new(String::from("a"), Some("b"), Color::Red, None, None, None);
-        let node_1_rc = Rc::new(RefCell::new(node_1));
-        let node_2 = Node::new(
-            String::from("a"),
-            Some("a"),
-            Color::Red,
-            None,
-            Some(Rc::clone(&node_1_rc)),
-            None,
-        );
-
-        assert!(Rc::ptr_eq(&node_1_rc, &node_2.get_right_child().unwrap()))
+    fn key_at(tree: &RBTree, path: &str) -> Vec<u8> {
+        node_at(tree, path).0
     }
 
-    #[test]
-    fn test_get_parent() {
-        let node_1 = Node::new(String::from("a"), Some("asd"), Color::Red, None, None, None);
-        let node_1_rc = Rc::new(RefCell::new(node_1));
-        let node_2 = Node::new(
-            String::from("a"),
-            Some("a"),
-            Color::Red,
-            None,
-            None,
-            Some(Rc::downgrade(&node_1_rc)),
-        );
-
-        assert!(Rc::ptr_eq(&node_1_rc, &node_2.get_parent().unwrap()))
+    fn color_at(tree: &RBTree, path: &str) -> Color {
+        node_at(tree, path).1
     }
 
     #[test]
@@ -79,15 +34,20 @@ mod tests {
         let sample_vec = [0; 20];
         let sample_vec: Vec<String> = sample_vec.iter().map(sample).collect();
 
-        let mut rb_tree = RBTree::new();
+        let rb_tree = RBTree::new();
 
         for key in &sample_vec {
-            rb_tree.insert(key, key);
+            rb_tree.put(key.as_bytes(), key.as_bytes(), 0);
         }
 
         for key in &sample_vec {
             assert!(
-                &rb_tree.search(key).unwrap().0 == key,
+                rb_tree
+                    .search(key.as_bytes(), MAX_SEQUENCE)
+                    .unwrap()
+                    .0
+                    .user_key()
+                    == key.as_bytes(),
                 "{}",
                 format!("Did not find key: {}", key)
             );
@@ -100,13 +60,13 @@ mod tests {
         let sample_vec = [0; 20];
         let sample_vec: Vec<String> = sample_vec.iter().map(sample).collect();
 
-        let mut rb_tree = RBTree::new();
+        let rb_tree = RBTree::new();
 
         for key in &sample_vec {
-            rb_tree.insert(key, key);
+            rb_tree.put(key.as_bytes(), key.as_bytes(), 0);
         }
 
-        assert_eq!(rb_tree.len(), 20);
+        assert_eq!(rb_tree.live_len(), 20);
     }
 
     #[test]
@@ -115,16 +75,17 @@ mod tests {
         let sample_vec: Vec<i32> = (0..20).collect();
         let sample_vec: Vec<&str> = sample_vec.iter().map(sample).collect();
 
-        let mut rb_tree = RBTree::new();
+        let rb_tree = RBTree::new();
 
         for key in sample_vec {
-            rb_tree.insert(key, key);
+            rb_tree.put(key.as_bytes(), key.as_bytes(), 0);
         }
 
-        assert_eq!(rb_tree.len(), 1);
-        let root_node = rb_tree.root.as_ref().unwrap();
-        assert_eq!(root_node.borrow().key, "a");
-        assert_eq!(root_node.borrow().value.as_ref().unwrap(), "a");
+        assert_eq!(rb_tree.live_len(), 1);
+        let tree = rb_tree.tree.read().unwrap();
+        let root_node = tree.node(tree.root);
+        assert_eq!(root_node.key.user_key(), b"a");
+        assert_eq!(root_node.value, b"a");
     }
 
     #[test]
@@ -134,23 +95,23 @@ mod tests {
 
         let zip_arr: Vec<(&char, &i32)> = char_arr.iter().zip(arr.iter()).collect();
 
-        let mut tree = RBTree::new();
+        let tree = RBTree::new();
 
         for (ch, _val) in zip_arr.iter() {
             let s = ch.to_string();
-            tree.insert(&s, &s);
+            tree.put(s.as_bytes(), s.as_bytes(), 0);
         }
 
         let mut char_arr = char_arr;
         char_arr.sort();
 
-        let tree_vec: Vec<(String, Option<String>)> = tree.iter().collect();
+        let tree_vec: Vec<Vec<u8>> = tree.iter().map(|entry| entry.key).collect();
 
         let mut i1 = 0;
         let mut i2 = 0;
 
         while i1 != char_arr.len() && i2 != tree_vec.len() {
-            assert_eq!(char_arr[i1].to_string(), tree_vec[i2].0);
+            assert_eq!(char_arr[i1].to_string().into_bytes(), tree_vec[i2]);
             i1 += 1;
             i2 += 1;
         }
@@ -158,99 +119,35 @@ mod tests {
 
     #[test]
     fn test_tree_node_arrangement() {
-        let mut tree = RBTree::new();
-        assert!(tree.root.is_none());
+        let tree = RBTree::new();
+        assert!(tree.is_empty());
 
         let rand_string_gen = || Alphanumeric.sample_string(&mut rand::thread_rng(), 20);
 
-        tree.insert("b", &rand_string_gen());
-        assert_eq!(tree.root.as_ref().unwrap().borrow().key, "b");
-
-        tree.insert("a", "ajsjhdaukukad");
-        assert_eq!(tree.root.as_ref().unwrap().borrow().key, "b");
-        assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_left_child()
-                .unwrap()
-                .borrow()
-                .key,
-            "a"
-        );
-
-        tree.insert("c", &rand_string_gen());
-        assert_eq!(tree.root.as_ref().unwrap().borrow().key, "b");
-        assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_left_child()
-                .unwrap()
-                .borrow()
-                .key,
-            "a"
-        );
-        assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_right_child()
-                .unwrap()
-                .borrow()
-                .key,
-            "c"
-        );
-
-        tree.insert("d", &rand_string_gen());
-        assert_eq!(tree.root.as_ref().unwrap().borrow().key, "b");
-        assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_left_child()
-                .unwrap()
-                .borrow()
-                .key,
-            "a"
-        );
-        let c_node = tree
-            .root
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .get_right_child()
-            .unwrap();
-        assert_eq!(c_node.borrow().key, "c");
-        assert_eq!(c_node.borrow().get_right_child().unwrap().borrow().key, "d");
-
-        tree.insert("e", &rand_string_gen());
-        assert_eq!(tree.root.as_ref().unwrap().borrow().key, "b");
-        assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_left_child()
-                .unwrap()
-                .borrow()
-                .key,
-            "a"
-        );
-        let d_node = tree
-            .root
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .get_right_child()
-            .unwrap();
-        assert_eq!(d_node.borrow().key, "d");
-        assert_eq!(d_node.borrow().get_left_child().unwrap().borrow().key, "c");
-        assert_eq!(d_node.borrow().get_right_child().unwrap().borrow().key, "e");
+        tree.put(b"b", rand_string_gen().as_bytes(), 0);
+        assert_eq!(key_at(&tree, ""), b"b");
+
+        tree.put(b"a", b"ajsjhdaukukad", 0);
+        assert_eq!(key_at(&tree, ""), b"b");
+        assert_eq!(key_at(&tree, "l"), b"a");
+
+        tree.put(b"c", rand_string_gen().as_bytes(), 0);
+        assert_eq!(key_at(&tree, ""), b"b");
+        assert_eq!(key_at(&tree, "l"), b"a");
+        assert_eq!(key_at(&tree, "r"), b"c");
+
+        tree.put(b"d", rand_string_gen().as_bytes(), 0);
+        assert_eq!(key_at(&tree, ""), b"b");
+        assert_eq!(key_at(&tree, "l"), b"a");
+        assert_eq!(key_at(&tree, "r"), b"c");
+        assert_eq!(key_at(&tree, "rr"), b"d");
+
+        tree.put(b"e", rand_string_gen().as_bytes(), 0);
+        assert_eq!(key_at(&tree, ""), b"b");
+        assert_eq!(key_at(&tree, "l"), b"a");
+        assert_eq!(key_at(&tree, "r"), b"d");
+        assert_eq!(key_at(&tree, "rl"), b"c");
+        assert_eq!(key_at(&tree, "rr"), b"e");
     }
 
     fn rand_string_gen() -> String {
@@ -259,154 +156,414 @@ mod tests {
 
     #[test]
     fn test_tree_color_arrangement() {
-        let mut tree = RBTree::new();
-        assert!(tree.root.is_none());
+        let tree = RBTree::new();
+        assert!(tree.is_empty());
+
+        tree.put(b"b", rand_string_gen().as_bytes(), 0);
+        assert_eq!(color_at(&tree, ""), Color::Black);
+
+        tree.put(b"a", rand_string_gen().as_bytes(), 0);
+        assert_eq!(color_at(&tree, ""), Color::Black);
+        assert_eq!(color_at(&tree, "l"), Color::Red);
+
+        tree.put(b"c", rand_string_gen().as_bytes(), 0);
+        assert_eq!(color_at(&tree, ""), Color::Black);
+        assert_eq!(color_at(&tree, "l"), Color::Red);
+        assert_eq!(color_at(&tree, "r"), Color::Red);
+
+        tree.put(b"d", rand_string_gen().as_bytes(), 0);
+        assert_eq!(color_at(&tree, ""), Color::Black);
+        assert_eq!(color_at(&tree, "l"), Color::Black);
+        assert_eq!(color_at(&tree, "r"), Color::Black);
+        assert_eq!(color_at(&tree, "rr"), Color::Red);
+
+        tree.put(b"e", rand_string_gen().as_bytes(), 0);
+        assert_eq!(color_at(&tree, ""), Color::Black);
+        assert_eq!(color_at(&tree, "l"), Color::Black);
+        assert_eq!(color_at(&tree, "r"), Color::Black);
+        assert_eq!(color_at(&tree, "rl"), Color::Red);
+        assert_eq!(color_at(&tree, "rr"), Color::Red);
+    }
 
-        tree.insert("b", &rand_string_gen());
-        assert_eq!(tree.root.as_ref().unwrap().borrow().color, Color::Black);
+    fn generate_rb_tree(len: usize) -> RBTree {
+        let tree = RBTree::new();
 
-        tree.insert("a", &rand_string_gen());
-        assert_eq!(tree.root.as_ref().unwrap().borrow().color, Color::Black);
-        assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_left_child()
-                .unwrap()
-                .borrow()
-                .color,
-            Color::Red
-        );
+        for _ in 0..len {
+            tree.put(
+                rand_string_gen().as_bytes(),
+                rand_string_gen().as_bytes(),
+                0,
+            );
+        }
 
-        tree.insert("c", &rand_string_gen());
-        assert_eq!(tree.root.as_ref().unwrap().borrow().color, Color::Black);
-        assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_left_child()
-                .unwrap()
-                .borrow()
-                .color,
-            Color::Red
-        );
-        assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_right_child()
-                .unwrap()
-                .borrow()
-                .color,
-            Color::Red
-        );
+        tree
+    }
+
+    #[test]
+    fn test_deletion() {
+        let rb_tree = generate_rb_tree(6);
+
+        rb_tree.put(b"a", b"abv", 0);
+        rb_tree.delete(b"a", 1);
+
+        let studd = rb_tree.search(b"a", MAX_SEQUENCE);
+
+        assert!(studd.unwrap().1.is_none());
+    }
+
+    // Checks the order, the parents and the colors below `node` and returns its black height.
+    fn check_subtree(tree: &Tree, node: Index, parent: Index) -> usize {
+        let Some(node_ref) = tree.get(node) else {
+            return 1;
+        };
+        assert_eq!(node_ref.parent, parent);
+        if node_ref.color == Color::Red {
+            assert_eq!(tree.color(node_ref.left), Color::Black);
+            assert_eq!(tree.color(node_ref.right), Color::Black);
+        }
+        if let Some(left) = tree.get(node_ref.left) {
+            assert!(left.key < node_ref.key);
+        }
+        if let Some(right) = tree.get(node_ref.right) {
+            assert!(right.key > node_ref.key);
+        }
+        let left_height = check_subtree(tree, node_ref.left, node);
+        let right_height = check_subtree(tree, node_ref.right, node);
+        assert_eq!(left_height, right_height);
+        left_height + usize::from(node_ref.color == Color::Black)
+    }
+
+    fn check_tree(tree: &RBTree) {
+        let tree = tree.tree.read().unwrap();
+        assert_eq!(tree.color(tree.root), Color::Black);
+        check_subtree(&tree, tree.root, NIL);
+    }
+
+    #[test]
+    fn test_remove() {
+        let tree = RBTree::new();
+        for key in 0..10u8 {
+            tree.put(&[key], &[key], 0);
+        }
+        assert!(tree.remove(&[3]));
+        assert!(!tree.remove(&[3]));
+        assert!(!tree.remove(&[10]));
+        assert_eq!(tree.search(&[3], MAX_SEQUENCE), None);
+        assert_eq!(tree.live_len(), 9);
+        let keys: Vec<Vec<u8>> = tree.iter().map(|entry| entry.key).collect();
+        assert_eq!(keys, [0, 1, 2, 4, 5, 6, 7, 8, 9].map(|key| vec![key]));
+        check_tree(&tree);
+
+        for key in 0..10u8 {
+            tree.remove(&[key]);
+            check_tree(&tree);
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.tree.read().unwrap().root, NIL);
+        assert_eq!(tree.approximate_memory_usage(), 0);
+    }
+
+    #[test]
+    fn test_remove_keeps_tree_balanced() {
+        let tree = RBTree::new();
+        let mut keys: Vec<[u8; 2]> = (0..500u16).map(|key| key.to_be_bytes()).collect();
+        for _ in 0..3 {
+            keys.shuffle(&mut rand::thread_rng());
+            for key in &keys {
+                tree.put(key, key, 0);
+            }
+            check_tree(&tree);
+            //Every other key, in random order
+            for key in keys.iter().step_by(2) {
+                assert!(tree.remove(key));
+                check_tree(&tree);
+            }
+            assert_eq!(tree.live_len(), 250);
+        }
+        for key in &keys {
+            tree.remove(key);
+        }
+        check_tree(&tree);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_removed_slots_are_reused() {
+        let tree = RBTree::new();
+        for key in 0..100u8 {
+            tree.put(&[key], &[key], 0);
+        }
+        for key in 0..50u8 {
+            tree.remove(&[key]);
+        }
+        for key in 100..150u8 {
+            tree.put(&[key], &[key], 0);
+        }
+        assert_eq!(tree.tree.read().unwrap().nodes.len(), 100);
+        assert_eq!(tree.live_len(), 100);
+        check_tree(&tree);
+    }
+
+    #[test]
+    fn test_write_while_iterating() {
+        let tree = RBTree::new();
+        for key in (0..10u8).step_by(2) {
+            tree.put(&[key], &[key], 0);
+        }
+        let mut iter = tree.iter();
+        assert_eq!(iter.next().unwrap().key, [0]);
+        //Ahead of the iterator, so it shows up
+        tree.put(&[1], &[1], 0);
+        tree.remove(&[2]);
+        assert_eq!(keys(&mut iter), [1, 4, 6, 8]);
+    }
+
+    #[test]
+    fn test_parallel_writes() {
+        fn shared<T: Send + Sync>(value: T) -> T {
+            value
+        }
+        let tree = shared(RBTree::new());
+        thread::scope(|s| {
+            for thread in 0..4u8 {
+                let tree = &tree;
+                s.spawn(move || {
+                    for key in (thread..200).step_by(4) {
+                        tree.put(&[key], &[key], key.into());
+                        assert_eq!(tree.get(&[key]), Some(vec![key]));
+                    }
+                    for key in (thread..200).step_by(8) {
+                        assert!(tree.remove(&[key]));
+                    }
+                });
+            }
+        });
+        assert_eq!(tree.live_len(), 100);
+        check_tree(&tree);
+    }
 
-        tree.insert("d", &rand_string_gen());
-        assert_eq!(tree.root.as_ref().unwrap().borrow().color, Color::Black);
+    #[test]
+    fn test_live_len_leaves_out_tombstones() {
+        let tree = RBTree::new();
+        tree.put(b"a", b"1", 1);
+        tree.put(b"b", b"2", 2);
+        tree.delete(b"b", 3);
+        tree.delete(b"c", 4);
+        //The value under the deletion of b is still there for older snapshots
+        assert_eq!(tree.live_len(), 2);
+        assert_eq!(tree.tombstones(), 2);
+        assert_eq!(tree.len(), 4);
+
+        tree.put(b"c", b"3", 5);
+        assert_eq!((tree.live_len(), tree.tombstones()), (3, 2));
+        assert!(tree.remove(b"b"));
+        assert_eq!((tree.live_len(), tree.tombstones()), (2, 1));
+        assert!(tree.remove(b"a"));
+        assert!(tree.remove(b"c"));
+        assert!(tree.is_empty());
+    }
+
+    fn keys(iter: impl Iterator<Item = Entry>) -> Vec<u8> {
+        iter.map(|entry| entry.key[0]).collect()
+    }
+
+    #[test]
+    fn test_range() {
+        let tree = RBTree::new();
+        for key in [5u8, 1, 8, 3, 9, 0, 2, 7, 4, 6] {
+            tree.put(&[key], &[key], 0);
+        }
+
+        assert_eq!(keys(tree.range(&[3][..]..&[6][..])), [3, 4, 5]);
+        assert_eq!(keys(tree.range(&[3][..]..=&[6][..])), [3, 4, 5, 6]);
+        assert_eq!(keys(tree.range(..&[2][..])), [0, 1]);
         assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_left_child()
-                .unwrap()
-                .borrow()
-                .color,
-            Color::Black
+            keys(tree.range((Bound::Excluded(&[2][..]), Bound::Unbounded))),
+            [3, 4, 5, 6, 7, 8, 9]
         );
-        assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_right_child()
-                .unwrap()
-                .borrow()
-                .color,
-            Color::Black
+        assert!(keys(tree.range(&[10][..]..)).is_empty());
+        assert!(
+            keys(tree.range((Bound::Included(&[6][..]), Bound::Excluded(&[3][..])))).is_empty()
         );
-        let c_node = tree
-            .root
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .get_right_child()
-            .unwrap();
+        assert!(keys(RBTree::new().iter()).is_empty());
+    }
+
+    #[test]
+    fn test_reverse_iteration() {
+        let tree = RBTree::new();
+        for key in [5u8, 1, 8, 3, 9, 0, 2, 7, 4, 6] {
+            tree.put(&[key], &[key], 0);
+        }
+
+        assert_eq!(keys(tree.iter().rev()), [9, 8, 7, 6, 5, 4, 3, 2, 1, 0]);
+        assert_eq!(keys(tree.range(&[2][..]..&[5][..]).rev()), [4, 3, 2]);
+
+        //Both ends meet without handing out a key twice
+        let mut iter = tree.range(&[2][..]..=&[6][..]);
+        assert_eq!(iter.next().unwrap().key, [2]);
+        assert_eq!(iter.next_back().unwrap().key, [6]);
+        assert_eq!(iter.next_back().unwrap().key, [5]);
+        assert_eq!(keys(&mut iter), [3, 4]);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    #[test]
+    fn test_seek() {
+        let tree = RBTree::new();
+        for key in (0..20u8).step_by(2) {
+            tree.put(&[key], &[key], 0);
+        }
+
+        let mut iter = tree.iter();
+        iter.seek(&[7]);
+        assert_eq!(iter.next().unwrap().key, [8]);
+        iter.seek(&[8]);
+        assert_eq!(iter.next().unwrap().key, [8]);
+        //Seeking goes back as well
+        iter.seek(&[0]);
+        assert_eq!(iter.next().unwrap().key, [0]);
+        iter.seek(&[19]);
+        assert_eq!(iter.next(), None);
+
+        //The range stays put
+        let mut iter = tree.range(&[4][..]..&[10][..]);
+        iter.seek(&[5]);
+        assert_eq!(keys(&mut iter), [6, 8]);
+        iter.seek(&[0]);
+        assert_eq!(keys(iter), [4, 6, 8]);
+    }
+
+    #[test]
+    fn test_prefix() {
+        let tree = RBTree::new();
+        for key in ["a", "ab", "abc", "abd", "ac", "b"] {
+            tree.put(key.as_bytes(), key.as_bytes(), 0);
+        }
+        tree.put(b"x\xff", b"", 0);
+        tree.put(b"x\xff\x01", b"", 0);
+        tree.put(b"y", b"", 0);
+
+        fn keys(iter: impl Iterator<Item = Entry>) -> Vec<Vec<u8>> {
+            iter.map(|entry| entry.key).collect()
+        }
+        assert_eq!(keys(tree.prefix(b"ab")), [&b"ab"[..], b"abc", b"abd"]);
+        assert_eq!(keys(tree.prefix(b"ab").rev()), [&b"abd"[..], b"abc", b"ab"]);
+        assert_eq!(keys(tree.prefix(b"abc")), [b"abc"]);
+        assert!(keys(tree.prefix(b"abe")).is_empty());
+        assert_eq!(keys(tree.prefix(b"")).len(), 9);
+        assert_eq!(keys(tree.prefix(b"x\xff")), [&b"x\xff"[..], b"x\xff\x01"]);
+        assert_eq!(keys(tree.prefix(b"\xff")), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn test_memtable_keeps_every_version() {
+        let tree = RBTree::new();
+        tree.put(b"a", b"1", 1);
+        tree.put(b"a", b"3", 3);
+        tree.put(b"a", b"2", 2);
+        Memtable::delete(&tree, b"b", 5);
+        tree.put(b"b", b"4", 4);
+        //The same write made again
+        tree.put(b"a", b"3", 3);
+
         assert_eq!(
-            c_node.borrow().get_right_child().unwrap().borrow().color,
-            Color::Red
+            tree.lookup(b"a", MAX_SEQUENCE),
+            Lookup::Found(b"3".to_vec())
         );
+        assert_eq!(tree.lookup(b"b", MAX_SEQUENCE), Lookup::Deleted);
+        assert_eq!(tree.lookup(b"c", MAX_SEQUENCE), Lookup::NotFound);
+        assert_eq!(tree.get(b"a"), Some(b"3".to_vec()));
+        assert_eq!(tree.get(b"b"), None);
+        assert_eq!(Memtable::len(&tree), 5);
+
+        //Older snapshots see the versions of their time
+        assert_eq!(tree.lookup(b"a", 2), Lookup::Found(b"2".to_vec()));
+        assert_eq!(tree.lookup(b"a", 1), Lookup::Found(b"1".to_vec()));
+        assert_eq!(tree.lookup(b"a", 0), Lookup::NotFound);
+        assert_eq!(tree.lookup(b"b", 4), Lookup::Found(b"4".to_vec()));
+        assert_eq!(tree.lookup(b"b", 3), Lookup::NotFound);
+        let versions: Vec<SequenceNumber> =
+            Memtable::iter(&tree).map(|entry| entry.sequence).collect();
+        assert_eq!(versions, [3, 2, 1, 5, 4]);
+    }
 
-        tree.insert("e", &rand_string_gen());
-        assert_eq!(tree.root.as_ref().unwrap().borrow().color, Color::Black);
+    #[test]
+    fn test_memtable_iter_and_range() {
+        let tree = RBTree::new();
+        for (seq, key) in [b"d", b"b", b"e", b"a", b"c"].iter().enumerate() {
+            tree.put(*key, *key, seq as SequenceNumber);
+        }
+        Memtable::delete(&tree, b"c", 10);
+
+        let keys = |entries: Entries| -> Vec<Vec<u8>> { entries.map(|entry| entry.key).collect() };
         assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_left_child()
-                .unwrap()
-                .borrow()
-                .color,
-            Color::Black
+            keys(Memtable::iter(&tree)),
+            [b"a", b"b", b"c", b"c", b"d", b"e"]
         );
         assert_eq!(
-            tree.root
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_right_child()
-                .unwrap()
-                .borrow()
-                .color,
-            Color::Black
+            keys(Memtable::range(&tree, &b"b"[..]..&b"d"[..])),
+            [b"b", b"c", b"c"]
         );
-        let d_node = tree
-            .root
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .get_right_child()
-            .unwrap();
         assert_eq!(
-            d_node.borrow().get_left_child().unwrap().borrow().color,
-            Color::Red
+            keys(Memtable::range(
+                &tree,
+                (Bound::Excluded(&b"b"[..]), Bound::Included(&b"d"[..]))
+            )),
+            [b"c", b"c", b"d"]
         );
         assert_eq!(
-            d_node.borrow().get_right_child().unwrap().borrow().color,
-            Color::Red
+            keys(Memtable::range(&tree, &b"bb"[..]..)),
+            [b"c", b"c", b"d", b"e"]
         );
-    }
-
-    fn generate_rb_tree(len: usize) -> RBTree {
-        let mut tree = RBTree::new();
-
-        for _ in 0..len {
-            tree.insert(&rand_string_gen(), &rand_string_gen());
-        }
+        assert_eq!(keys(Memtable::range(&tree, ..=&b"a"[..])), [b"a"]);
+        assert!(keys(Memtable::range(&tree, &b"f"[..]..)).is_empty());
+        let reversed: Vec<Vec<u8>> = Memtable::iter(&tree).rev().map(|entry| entry.key).collect();
+        assert_eq!(reversed, [b"e", b"d", b"c", b"c", b"b", b"a"]);
 
-        tree
+        let deleted = Memtable::range(&tree, &b"c"[..]..).next().unwrap();
+        assert_eq!(
+            deleted,
+            Entry {
+                key: b"c".to_vec(),
+                sequence: 10,
+                value: None
+            }
+        );
     }
 
     #[test]
-    fn test_deletion() {
-        let mut rb_tree = generate_rb_tree(6);
-
-        rb_tree.insert("a", "abv");
-        rb_tree.delete("a");
-
-        let studd = rb_tree.search("a");
-
-        assert!(studd.unwrap().1.is_none());
+    fn test_memtable_memory_usage() {
+        let tree = RBTree::new();
+        assert_eq!(tree.approximate_memory_usage(), 0);
+
+        tree.put(b"key", &[0; 100], 1);
+        let one = tree.approximate_memory_usage();
+        assert!(one > 103);
+        //Newer versions do not take the place of older ones
+        tree.put(b"key", &[0; 10], 2);
+        assert_eq!(tree.approximate_memory_usage(), 2 * one - 90);
+        tree.put(b"key", &[0; 10], 2);
+        assert_eq!(tree.approximate_memory_usage(), 2 * one - 90);
+        tree.put(b"other", &[0; 100], 3);
+        assert_eq!(tree.approximate_memory_usage(), 3 * one - 90 + 2);
     }
 }
 
-use std::cell::RefCell;
+use crate::key::version_bounds;
+use crate::{Entry, InternalKey, Lookup, Memtable, SequenceNumber, ValueType, MAX_SEQUENCE};
 use std::cmp::Ordering;
-use std::rc::{Rc, Weak};
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::sync::RwLock;
+
+// Index of a node in the arena of its tree
+type Index = u32;
+
+//Stands for a missing node, black like the leaves of a red-black tree
+const NIL: Index = Index::MAX;
+
+//Bytes taken up by a node besides its key and value: its slot in the arena
+const NODE_OVERHEAD: usize = mem::size_of::<Node>();
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Color {
@@ -416,376 +573,668 @@ pub enum Color {
 
 #[derive(Debug)]
 pub struct Node {
-    pub key: String,
-    pub value: Option<String>,
+    pub key: InternalKey,
+    //Empty for a deletion
+    pub value: Vec<u8>,
     color: Color,
-    pub left: Option<Rc<RefCell<Node>>>,
-    pub right: Option<Rc<RefCell<Node>>>,
-    pub parent: Option<Weak<RefCell<Node>>>,
+    left: Index,
+    right: Index,
+    parent: Index,
 }
 
-impl Node {
-    pub fn new(
-        key: String,
-        value: Option<&str>,
-        color: Color,
-        left: Option<Rc<RefCell<Node>>>,
-        right: Option<Rc<RefCell<Node>>>,
-        parent: Option<Weak<RefCell<Node>>>,
-    ) -> Node {
-        if let Some(value) = value {
-            Node {
-                key,
-                value: Some(String::from(value)),
-                color,
-                left,
-                right,
-                parent,
-            }
-        } else {
-            Node {
-                key,
-                value: None,
-                color,
-                left,
-                right,
-                parent,
-            }
+/// Red-black tree of the writes to byte keys, ordered by their [`InternalKey`]. Deleting a
+/// key adds a tombstone, which is what a [`Memtable`] needs, removing it takes every version
+/// of it out of the tree altogether.
+///
+/// Writes take `&self` like those of [`Memtable`]. The tree can be shared between threads,
+/// writes wait for each other and for reads to finish.
+#[derive(Debug, Default)]
+pub struct RBTree {
+    tree: RwLock<Tree>,
+}
+
+impl RBTree {
+    pub fn new() -> RBTree {
+        RBTree::default()
+    }
+
+    /// Number of entries that hold a value, unlike [`Memtable::len`] tombstones are not
+    /// counted.
+    pub fn live_len(&self) -> usize {
+        let tree = self.tree.read().unwrap();
+        tree.entries - tree.tombstones
+    }
+
+    /// Number of deletions, each of which left a tombstone behind.
+    pub fn tombstones(&self) -> usize {
+        self.tree.read().unwrap().tombstones
+    }
+
+    /// Latest write to `key` with a sequence number of at most `snapshot`, as its internal key
+    /// and its value, `None` for a deletion.
+    pub fn search(
+        &self,
+        key: &[u8],
+        snapshot: SequenceNumber,
+    ) -> Option<(InternalKey, Option<Vec<u8>>)> {
+        let tree = self.tree.read().unwrap();
+        let lookup = InternalKey::lookup(key, snapshot);
+        let node = tree.get(tree.lower_bound(Bound::Included(&lookup)))?;
+        if node.key.user_key() != key {
+            return None;
         }
+        Some((node.key.clone(), node_value(node)))
     }
 
-    pub fn get_color(&self) -> Color {
-        self.color
+    /// Takes every version of `key` out of the tree, tombstones and all. Returns whether
+    /// there was any.
+    pub fn remove(&self, key: &[u8]) -> bool {
+        self.tree.write().unwrap().remove(key)
     }
 
-    pub fn update_color(&mut self, new_color: Color) {
-        self.color = new_color;
+    /// Entries with a key starting with `prefix` in the order of [`Memtable::iter`].
+    pub fn prefix(&self, prefix: &[u8]) -> Entries<'_> {
+        let end = prefix_end(prefix);
+        let (start, end) = version_bounds(Bound::Included(prefix), end.as_ref().map(Vec::as_slice));
+        Entries {
+            nodes: Succesor::new(&self.tree, start, end),
+        }
     }
+}
 
-    pub fn get_left_child(&self) -> Option<Rc<RefCell<Node>>> {
-        self.left.as_ref().map(Rc::clone)
+impl Memtable for RBTree {
+    type Iter<'a> = Entries<'a>;
+
+    fn lookup(&self, key: &[u8], snapshot: SequenceNumber) -> Lookup {
+        match self.search(key, snapshot) {
+            Some((_, Some(val))) => Lookup::Found(val),
+            Some((_, None)) => Lookup::Deleted,
+            None => Lookup::NotFound,
+        }
     }
 
-    pub fn get_right_child(&self) -> Option<Rc<RefCell<Node>>> {
-        self.right.as_ref().map(Rc::clone)
+    fn put(&self, key: &[u8], val: &[u8], seq: SequenceNumber) {
+        let key = InternalKey::new(key, seq, ValueType::Value);
+        self.tree.write().unwrap().insert_generic(key, val);
     }
 
-    pub fn get_parent(&self) -> Option<Rc<RefCell<Node>>> {
-        if let Some(parent) = &self.parent {
-            parent.upgrade()
-        } else {
-            None
+    fn delete(&self, key: &[u8], seq: SequenceNumber) {
+        let key = InternalKey::new(key, seq, ValueType::Deletion);
+        self.tree.write().unwrap().insert_generic(key, &[]);
+    }
+
+    fn len(&self) -> usize {
+        self.tree.read().unwrap().entries
+    }
+
+    fn approximate_memory_usage(&self) -> usize {
+        self.tree.read().unwrap().memory
+    }
+
+    fn iter(&self) -> Entries<'_> {
+        self.range(..)
+    }
+
+    fn range<'k, R: RangeBounds<&'k [u8]>>(&self, range: R) -> Entries<'_> {
+        let (start, end) = version_bounds(range.start_bound().cloned(), range.end_bound().cloned());
+        Entries {
+            nodes: Succesor::new(&self.tree, start, end),
         }
     }
 }
 
-#[derive(Debug, Default)]
-pub struct RBTree {
-    root: Option<Rc<RefCell<Node>>>,
-    adjacency_list: Vec<Rc<RefCell<Node>>>,
+// Nodes live in `nodes` and point to each other by index, removed ones leave their slot to
+// the next node added.
+#[derive(Debug)]
+struct Tree {
+    nodes: Vec<Node>,
+    //Slots of removed nodes
+    free: Vec<Index>,
+    root: Index,
+    entries: usize,
+    //Deletions
+    tombstones: usize,
+    //Bytes taken up by the nodes, keys and values
+    memory: usize,
 }
 
-impl RBTree {
-    pub fn new() -> RBTree {
-        RBTree {
-            root: None,
-            adjacency_list: vec![],
+impl Default for Tree {
+    fn default() -> Tree {
+        Tree {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: NIL,
+            entries: 0,
+            tombstones: 0,
+            memory: 0,
         }
     }
+}
 
-    pub fn len(&self) -> usize {
-        self.adjacency_list.len()
+impl Tree {
+    fn node(&self, index: Index) -> &Node {
+        &self.nodes[index as usize]
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.root.is_none()
+    fn node_mut(&mut self, index: Index) -> &mut Node {
+        &mut self.nodes[index as usize]
     }
 
-    fn search_node(&self, key: &str) -> Option<Rc<RefCell<Node>>> {
-        let mut iter: Option<Rc<RefCell<Node>>> = self.root.as_ref().cloned();
-
-        while let Some(iter_node) = iter {
-            if key == iter_node.borrow().key {
-                return Some(iter_node);
-            } else if key > &iter_node.borrow().key {
-                iter = iter_node.borrow().get_right_child();
-            } else {
-                iter = iter_node.borrow().get_left_child();
-            }
+    fn get(&self, index: Index) -> Option<&Node> {
+        if index == NIL {
+            None
+        } else {
+            Some(self.node(index))
         }
-        None
     }
 
-    pub fn search(&self, key: &str) -> Option<(String, Option<String>)> {
-        let node = self.search_node(key);
-        node.map(|node| (node.borrow().key.clone(), node.borrow().value.clone()))
+    fn left(&self, index: Index) -> Index {
+        self.node(index).left
     }
 
-    pub fn delete(&mut self, key: &str) {
-        self.insert_generic(key, None);
+    fn right(&self, index: Index) -> Index {
+        self.node(index).right
     }
 
-    pub fn insert(&mut self, key: &str, val: &str) {
-        self.insert_generic(key, Some(val));
+    fn parent(&self, index: Index) -> Index {
+        self.node(index).parent
     }
 
-    fn insert_generic(&mut self, key: &str, val: Option<&str>) {
-        let mut leaf_node: Option<Rc<RefCell<Node>>> = None;
-        let mut iter: Option<Rc<RefCell<Node>>> = self.root.as_ref().cloned();
+    fn color(&self, index: Index) -> Color {
+        self.get(index).map_or(Color::Black, |node| node.color)
+    }
 
-        while let Some(iter_node) = iter {
-            leaf_node = Some(Rc::clone(&iter_node));
+    fn set_color(&mut self, index: Index, color: Color) {
+        self.node_mut(index).color = color;
+    }
 
-            match key.cmp(&iter_node.borrow().key) {
-                Ordering::Equal => {
-                    unsafe {
-                        (*iter_node.as_ptr()).value = val.map(String::from);
-                    }
-                    return;
-                }
-                Ordering::Less => {
-                    iter = iter_node.borrow().get_left_child();
-                }
-                Ordering::Greater => {
-                    iter = iter_node.borrow().get_right_child();
-                }
+    // First node whose key is not below `start`.
+    fn lower_bound(&self, start: Bound<&InternalKey>) -> Index {
+        let mut found = NIL;
+        let mut iter = self.root;
+
+        while let Some(iter_node) = self.get(iter) {
+            let above = match start {
+                Bound::Included(start) => iter_node.key >= *start,
+                Bound::Excluded(start) => iter_node.key > *start,
+                Bound::Unbounded => true,
+            };
+            if above {
+                found = iter;
+                iter = iter_node.left;
+            } else {
+                iter = iter_node.right;
             }
         }
+        found
+    }
 
-        let string = String::from(key);
-        let new_node = Node::new(string, val, Color::Red, None, None, None);
-        let new_node_rc = Rc::new(RefCell::new(new_node));
-
-        if let Some(leaf) = leaf_node.as_ref() {
-            if new_node_rc.borrow().key < leaf.borrow().key {
-                leaf.borrow_mut().left = Some(Rc::clone(&new_node_rc));
+    // Last node whose key is not above `end`.
+    fn upper_bound(&self, end: Bound<&InternalKey>) -> Index {
+        let mut found = NIL;
+        let mut iter = self.root;
+
+        while let Some(iter_node) = self.get(iter) {
+            let below = match end {
+                Bound::Included(end) => iter_node.key <= *end,
+                Bound::Excluded(end) => iter_node.key < *end,
+                Bound::Unbounded => true,
+            };
+            if below {
+                found = iter;
+                iter = iter_node.right;
             } else {
-                leaf.borrow_mut().right = Some(Rc::clone(&new_node_rc));
+                iter = iter_node.left;
+            }
+        }
+        found
+    }
+
+    fn find_smallest(&self, index: Index) -> Index {
+        let mut smallest = index;
+        while self.left(smallest) != NIL {
+            smallest = self.left(smallest);
+        }
+        smallest
+    }
+
+    // Adds the write `key` stands for, unless it is in the tree already. `val` is ignored for
+    // a deletion.
+    fn insert_generic(&mut self, key: InternalKey, val: &[u8]) {
+        let mut leaf_node = NIL;
+        let mut iter = self.root;
+
+        while iter != NIL {
+            leaf_node = iter;
+
+            let node = self.node(iter);
+            match key.cmp(&node.key) {
+                //The same write made again
+                Ordering::Equal => return,
+                Ordering::Less => iter = node.left,
+                Ordering::Greater => iter = node.right,
             }
-            new_node_rc.borrow_mut().parent = Some(Rc::downgrade(leaf));
+        }
+
+        let deletion = key.value_type() == ValueType::Deletion;
+        let value = if deletion { Vec::new() } else { val.to_vec() };
+        self.memory += NODE_OVERHEAD + key.user_key().len() + value.len();
+        self.entries += 1;
+        if deletion {
+            self.tombstones += 1;
+        }
+        let left_of_leaf = leaf_node != NIL && key < self.node(leaf_node).key;
+        let new_node = self.allocate(Node {
+            key,
+            value,
+            color: Color::Red,
+            left: NIL,
+            right: NIL,
+            parent: leaf_node,
+        });
+
+        if leaf_node == NIL {
+            self.root = new_node;
+        } else if left_of_leaf {
+            self.node_mut(leaf_node).left = new_node;
         } else {
-            self.root = Some(Rc::clone(&new_node_rc));
+            self.node_mut(leaf_node).right = new_node;
         }
 
-        self.adjacency_list.push(Rc::clone(&new_node_rc));
+        self.insert_fixup(new_node);
+    }
 
-        self.insert_fixup(new_node_rc);
+    fn allocate(&mut self, node: Node) -> Index {
+        if let Some(index) = self.free.pop() {
+            self.nodes[index as usize] = node;
+            return index;
+        }
+        let index = Index::try_from(self.nodes.len())
+            .ok()
+            .filter(|&index| index != NIL)
+            .expect("too many nodes in the tree");
+        self.nodes.push(node);
+        index
     }
 
-    fn insert_fixup(&mut self, new_node: Rc<RefCell<Node>>) {
+    fn insert_fixup(&mut self, new_node: Index) {
         let mut curr_node = new_node;
-        while curr_node.borrow().get_parent().is_some()
-            && curr_node
-                .borrow()
-                .get_parent()
-                .unwrap()
-                .borrow()
-                .get_color()
-                == Color::Red
-        {
-            let curr_node_p = curr_node.borrow().get_parent().expect("No parent found");
-            let curr_node_gp = curr_node_p
-                .borrow()
-                .get_parent()
-                .expect("No grand parent found"); //grand parent is gauranteed to exist because we have parent's color red but root always have black color
-            if curr_node_gp.borrow().get_left_child().is_some()
-                && Rc::ptr_eq(
-                    &curr_node_p,
-                    &curr_node_gp
-                        .borrow()
-                        .get_left_child()
-                        .expect("Left child of grandparent does not exist"),
-                )
-            {
-                let uncle_option: Option<Rc<RefCell<Node>>> =
-                    curr_node_gp.borrow().get_right_child();
-                match uncle_option {
-                    Some(uncle) if matches!(uncle.borrow().get_color(), Color::Red) => {
-                        curr_node_p.borrow_mut().update_color(Color::Black);
-                        uncle.borrow_mut().update_color(Color::Black);
-                        curr_node_gp.borrow_mut().update_color(Color::Red);
+        while self.color(self.parent(curr_node)) == Color::Red {
+            let curr_node_p = self.parent(curr_node);
+            //grand parent is gauranteed to exist because we have parent's color red but root
+            //always have black color
+            let curr_node_gp = self.parent(curr_node_p);
+            if curr_node_p == self.left(curr_node_gp) {
+                let uncle = self.right(curr_node_gp);
+                if self.color(uncle) == Color::Red {
+                    self.set_color(curr_node_p, Color::Black);
+                    self.set_color(uncle, Color::Black);
+                    self.set_color(curr_node_gp, Color::Red);
+                    //The grandparent may now be a red child of a red node
+                    curr_node = curr_node_gp;
+                } else {
+                    if curr_node == self.right(curr_node_p) {
+                        curr_node = curr_node_p;
+                        self.left_rotate(curr_node);
                     }
-                    _ => {
-                        if curr_node_p.borrow().get_right_child().is_some()
-                            && Rc::ptr_eq(
-                                &curr_node,
-                                &curr_node_p.borrow().get_right_child().unwrap(),
-                            )
-                        {
-                            let temp = Rc::clone(&curr_node_p);
-                            curr_node = temp;
-                            self.left_rotate(Rc::clone(&curr_node));
-                        }
-                        let curr_node_p = curr_node.borrow().get_parent().unwrap();
-                        curr_node_p.borrow_mut().update_color(Color::Black);
-                        let curr_node_gp = curr_node_p.borrow().get_parent().unwrap();
-                        curr_node_gp.borrow_mut().update_color(Color::Red);
-                        self.right_rotate(curr_node_gp);
-                    }
-                };
+                    let curr_node_p = self.parent(curr_node);
+                    self.set_color(curr_node_p, Color::Black);
+                    let curr_node_gp = self.parent(curr_node_p);
+                    self.set_color(curr_node_gp, Color::Red);
+                    self.right_rotate(curr_node_gp);
+                }
             } else {
-                let uncle_option: Option<Rc<RefCell<Node>>> =
-                    curr_node_gp.borrow().get_left_child();
-                match uncle_option {
-                    Some(uncle) if matches!(uncle.borrow().get_color(), Color::Red) => {
-                        curr_node_p.borrow_mut().update_color(Color::Black);
-                        uncle.borrow_mut().update_color(Color::Black);
-                        curr_node_gp.borrow_mut().update_color(Color::Red);
-                    }
-                    _ => {
-                        if curr_node_p.borrow().get_left_child().is_some()
-                            && Rc::ptr_eq(
-                                &curr_node,
-                                &curr_node_p.borrow().get_left_child().unwrap(),
-                            )
-                        {
-                            let temp = curr_node.borrow().get_parent().unwrap();
-                            curr_node = temp;
-                            self.right_rotate(Rc::clone(&curr_node));
-                        }
-                        let curr_node_p = curr_node.borrow().get_parent().unwrap();
-                        curr_node_p.borrow_mut().update_color(Color::Black);
-                        let curr_node_gp = curr_node_p.borrow().get_parent().unwrap();
-                        curr_node_gp.borrow_mut().update_color(Color::Red);
-                        self.left_rotate(curr_node_gp);
+                let uncle = self.left(curr_node_gp);
+                if self.color(uncle) == Color::Red {
+                    self.set_color(curr_node_p, Color::Black);
+                    self.set_color(uncle, Color::Black);
+                    self.set_color(curr_node_gp, Color::Red);
+                    curr_node = curr_node_gp;
+                } else {
+                    if curr_node == self.left(curr_node_p) {
+                        curr_node = curr_node_p;
+                        self.right_rotate(curr_node);
                     }
-                };
+                    let curr_node_p = self.parent(curr_node);
+                    self.set_color(curr_node_p, Color::Black);
+                    let curr_node_gp = self.parent(curr_node_p);
+                    self.set_color(curr_node_gp, Color::Red);
+                    self.left_rotate(curr_node_gp);
+                }
             }
         }
 
-        if self.root.is_some() {
-            self.root
-                .as_ref()
-                .unwrap()
-                .borrow_mut()
-                .update_color(Color::Black);
+        if self.root != NIL {
+            self.set_color(self.root, Color::Black);
         }
     }
 
-    fn left_rotate(&mut self, parent_node: Rc<RefCell<Node>>) {
-        let right_child = parent_node.borrow().get_right_child().unwrap();
-        if let Some(left) = right_child.borrow().get_left_child() {
-            left.borrow_mut().parent = Some(Rc::downgrade(&parent_node));
-            parent_node.borrow_mut().right = Some(left);
+    // Removes every version of `key`.
+    fn remove(&mut self, key: &[u8]) -> bool {
+        let newest = InternalKey::lookup(key, MAX_SEQUENCE);
+        let mut removed = false;
+        loop {
+            let node = self.lower_bound(Bound::Included(&newest));
+            match self.get(node) {
+                Some(found) if found.key.user_key() == key => self.remove_node(node),
+                _ => return removed,
+            }
+            removed = true;
+        }
+    }
+
+    fn remove_node(&mut self, node: Index) {
+        //The node taken out of its place, and the one that moves into it along with its parent
+        let (removed_color, moved, moved_parent) = if self.left(node) == NIL {
+            let moved = self.right(node);
+            let parent = self.parent(node);
+            self.transplant(node, moved);
+            (self.color(node), moved, parent)
+        } else if self.right(node) == NIL {
+            let moved = self.left(node);
+            let parent = self.parent(node);
+            self.transplant(node, moved);
+            (self.color(node), moved, parent)
         } else {
-            parent_node.borrow_mut().right = None;
+            //The successor of the node takes its place instead
+            let right = self.right(node);
+            let successor = self.find_smallest(right);
+            let successor_color = self.color(successor);
+            let moved = self.right(successor);
+            let moved_parent = if successor == right {
+                successor
+            } else {
+                let parent = self.parent(successor);
+                self.transplant(successor, moved);
+                self.node_mut(successor).right = right;
+                self.node_mut(right).parent = successor;
+                parent
+            };
+            self.transplant(node, successor);
+            let left = self.left(node);
+            self.node_mut(successor).left = left;
+            self.node_mut(left).parent = successor;
+            self.set_color(successor, self.color(node));
+            (successor_color, moved, moved_parent)
+        };
+
+        if removed_color == Color::Black {
+            self.remove_fixup(moved, moved_parent);
         }
 
-        if let Some(grand_parent) = parent_node.borrow().get_parent() {
-            right_child.borrow_mut().parent = Some(Rc::downgrade(&grand_parent));
+        let removed = self.node_mut(node);
+        let key = mem::replace(
+            &mut removed.key,
+            InternalKey::new(&[], 0, ValueType::Deletion),
+        );
+        let value = mem::take(&mut removed.value);
+        self.memory -= NODE_OVERHEAD + key.user_key().len() + value.len();
+        self.entries -= 1;
+        if key.value_type() == ValueType::Deletion {
+            self.tombstones -= 1;
+        }
+        self.free.push(node);
+    }
+
+    // Puts `new_node` where `old_node` is under its parent.
+    fn transplant(&mut self, old_node: Index, new_node: Index) {
+        let parent = self.parent(old_node);
+        if parent == NIL {
+            self.root = new_node;
+        } else if old_node == self.left(parent) {
+            self.node_mut(parent).left = new_node;
+        } else {
+            self.node_mut(parent).right = new_node;
+        }
+        if new_node != NIL {
+            self.node_mut(new_node).parent = parent;
+        }
+    }
 
-            if grand_parent.borrow().get_left_child().is_some()
-                && Rc::ptr_eq(
-                    &parent_node,
-                    &grand_parent.borrow().get_left_child().unwrap(),
-                )
-            {
-                grand_parent.borrow_mut().left = Some(Rc::clone(&right_child));
+    // Restores the colors after a black node was taken out from above `node`, which is one
+    // black node short on every path through it. `node` may be a missing child of `parent`.
+    fn remove_fixup(&mut self, mut node: Index, mut parent: Index) {
+        //A black node short at the root is no longer short of anything
+        while node != self.root && self.color(node) == Color::Black {
+            let curr_node_p = parent;
+            if node == self.left(curr_node_p) {
+                //The sibling has a black node on every path through it, so it exists
+                let mut sibling = self.right(curr_node_p);
+                if self.color(sibling) == Color::Red {
+                    self.set_color(sibling, Color::Black);
+                    self.set_color(curr_node_p, Color::Red);
+                    self.left_rotate(curr_node_p);
+                    sibling = self.right(curr_node_p);
+                }
+                if self.color(self.left(sibling)) == Color::Black
+                    && self.color(self.right(sibling)) == Color::Black
+                {
+                    self.set_color(sibling, Color::Red);
+                    node = curr_node_p;
+                    parent = self.parent(curr_node_p);
+                } else {
+                    if self.color(self.right(sibling)) == Color::Black {
+                        self.set_color(self.left(sibling), Color::Black);
+                        self.set_color(sibling, Color::Red);
+                        self.right_rotate(sibling);
+                        sibling = self.right(curr_node_p);
+                    }
+                    self.set_color(sibling, self.color(curr_node_p));
+                    self.set_color(curr_node_p, Color::Black);
+                    self.set_color(self.right(sibling), Color::Black);
+                    self.left_rotate(curr_node_p);
+                    node = self.root;
+                }
             } else {
-                grand_parent.borrow_mut().right = Some(Rc::clone(&right_child));
+                let mut sibling = self.left(curr_node_p);
+                if self.color(sibling) == Color::Red {
+                    self.set_color(sibling, Color::Black);
+                    self.set_color(curr_node_p, Color::Red);
+                    self.right_rotate(curr_node_p);
+                    sibling = self.left(curr_node_p);
+                }
+                if self.color(self.left(sibling)) == Color::Black
+                    && self.color(self.right(sibling)) == Color::Black
+                {
+                    self.set_color(sibling, Color::Red);
+                    node = curr_node_p;
+                    parent = self.parent(curr_node_p);
+                } else {
+                    if self.color(self.left(sibling)) == Color::Black {
+                        self.set_color(self.right(sibling), Color::Black);
+                        self.set_color(sibling, Color::Red);
+                        self.left_rotate(sibling);
+                        sibling = self.left(curr_node_p);
+                    }
+                    self.set_color(sibling, self.color(curr_node_p));
+                    self.set_color(curr_node_p, Color::Black);
+                    self.set_color(self.left(sibling), Color::Black);
+                    self.right_rotate(curr_node_p);
+                    node = self.root;
+                }
             }
+        }
+
+        if node != NIL {
+            self.set_color(node, Color::Black);
+        }
+    }
+
+    fn left_rotate(&mut self, parent_node: Index) {
+        let right_child = self.right(parent_node);
+        let left = self.left(right_child);
+        self.node_mut(parent_node).right = left;
+        if left != NIL {
+            self.node_mut(left).parent = parent_node;
+        }
+
+        let grand_parent = self.parent(parent_node);
+        self.node_mut(right_child).parent = grand_parent;
+        if grand_parent == NIL {
+            self.root = right_child;
+        } else if parent_node == self.left(grand_parent) {
+            self.node_mut(grand_parent).left = right_child;
         } else {
-            right_child.borrow_mut().parent = None;
-            self.root = Some(Rc::clone(&right_child));
+            self.node_mut(grand_parent).right = right_child;
         }
 
-        right_child.borrow_mut().left = Some(Rc::clone(&parent_node));
-        parent_node.borrow_mut().parent = Some(Rc::downgrade(&right_child));
+        self.node_mut(right_child).left = parent_node;
+        self.node_mut(parent_node).parent = right_child;
     }
 
-    fn right_rotate(&mut self, parent_node: Rc<RefCell<Node>>) {
-        let left_child = parent_node.borrow().get_left_child().unwrap();
-        if let Some(right) = left_child.borrow().get_right_child() {
-            right.borrow_mut().parent = Some(Rc::downgrade(&parent_node));
-            parent_node.borrow_mut().left = Some(right);
+    fn right_rotate(&mut self, parent_node: Index) {
+        let left_child = self.left(parent_node);
+        let right = self.right(left_child);
+        self.node_mut(parent_node).left = right;
+        if right != NIL {
+            self.node_mut(right).parent = parent_node;
+        }
+
+        let grand_parent = self.parent(parent_node);
+        self.node_mut(left_child).parent = grand_parent;
+        if grand_parent == NIL {
+            self.root = left_child;
+        } else if parent_node == self.right(grand_parent) {
+            self.node_mut(grand_parent).right = left_child;
         } else {
-            parent_node.borrow_mut().left = None;
+            self.node_mut(grand_parent).left = left_child;
         }
 
-        if let Some(grand_parent) = parent_node.borrow().get_parent() {
-            left_child.borrow_mut().parent = Some(Rc::downgrade(&grand_parent));
+        self.node_mut(left_child).right = parent_node;
+        self.node_mut(parent_node).parent = left_child;
+    }
+}
 
-            if grand_parent.borrow().get_right_child().is_some()
-                && Rc::ptr_eq(
-                    &parent_node,
-                    &grand_parent.borrow().get_right_child().unwrap(),
-                )
-            {
-                grand_parent.borrow_mut().right = Some(Rc::clone(&left_child));
-            } else {
-                grand_parent.borrow_mut().left = Some(Rc::clone(&left_child));
-            }
+// Nodes of an [`RBTree`] in key order, from either end.
+//
+// Every step looks its key up from the root under a read lock, so the tree can be written
+// to while iterating. Keys written meanwhile show up if they are still ahead of the
+// iterator.
+struct Succesor<'a> {
+    tree: &'a RwLock<Tree>,
+    //Start of the range, for seeking
+    range_start: Bound<InternalKey>,
+    //Moved past every key handed out from the front and the back, so that the ends do not
+    //cross
+    start: Bound<InternalKey>,
+    end: Bound<InternalKey>,
+}
+
+impl<'a> Succesor<'a> {
+    fn new(
+        tree: &'a RwLock<Tree>,
+        start: Bound<InternalKey>,
+        end: Bound<InternalKey>,
+    ) -> Succesor<'a> {
+        Succesor {
+            tree,
+            range_start: start.clone(),
+            start,
+            end,
+        }
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        let newest = InternalKey::lookup(key, MAX_SEQUENCE);
+        self.start = if after_start(&self.range_start, &newest) {
+            Bound::Included(newest)
         } else {
-            left_child.borrow_mut().parent = None;
-            self.root = Some(Rc::clone(&left_child));
+            self.range_start.clone()
+        };
+    }
+
+    // Hands the node at the front to `read` and moves the front past it.
+    fn next_node<T>(&mut self, read: impl FnOnce(&Node) -> T) -> Option<T> {
+        let tree = self.tree.read().unwrap();
+        let node = tree.get(tree.lower_bound(self.start.as_ref()))?;
+        if !before_end(&self.end, &node.key) {
+            return None;
         }
+        self.start = Bound::Excluded(node.key.clone());
+        Some(read(node))
+    }
 
-        left_child.borrow_mut().right = Some(Rc::clone(&parent_node));
-        parent_node.borrow_mut().parent = Some(Rc::downgrade(&left_child));
+    // Hands the node at the back to `read` and moves the back past it.
+    fn next_back_node<T>(&mut self, read: impl FnOnce(&Node) -> T) -> Option<T> {
+        let tree = self.tree.read().unwrap();
+        let node = tree.get(tree.upper_bound(self.end.as_ref()))?;
+        if !after_start(&self.start, &node.key) {
+            return None;
+        }
+        self.end = Bound::Excluded(node.key.clone());
+        Some(read(node))
     }
+}
 
-    pub fn iter(&self) -> Succesor {
-        Succesor::new(self.root.as_ref())
+fn after_start(start: &Bound<InternalKey>, key: &InternalKey) -> bool {
+    match start {
+        Bound::Included(start) => key >= start,
+        Bound::Excluded(start) => key > start,
+        Bound::Unbounded => true,
     }
 }
 
-pub struct Succesor {
-    node: Option<Rc<RefCell<Node>>>, //TODO: node should be a reference to Node. There is no point in keeping serving nodes when tree is removed.
+fn before_end(end: &Bound<InternalKey>, key: &InternalKey) -> bool {
+    match end {
+        Bound::Included(end) => key <= end,
+        Bound::Excluded(end) => key < end,
+        Bound::Unbounded => true,
+    }
 }
 
-impl Succesor {
-    fn new(node: Option<&Rc<RefCell<Node>>>) -> Succesor {
-        if let Some(node) = node {
-            Succesor {
-                node: Some(Self::find_smallest(Rc::clone(node))),
-            }
-        } else {
-            Succesor { node: None }
+// First key after every key starting with `prefix`, unbounded if there is none.
+fn prefix_end(prefix: &[u8]) -> Bound<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Bound::Excluded(end);
         }
     }
+    Bound::Unbounded
+}
 
-    fn find_smallest(node: Rc<RefCell<Node>>) -> Rc<RefCell<Node>> {
-        let mut smallest = node;
-        let mut node = smallest.borrow().get_left_child();
+/// Entries of an [`RBTree`] in key order, from either end, see [`Memtable::iter`].
+///
+/// The tree can be written to while iterating, entries written meanwhile show up if they are
+/// still ahead of the iterator.
+pub struct Entries<'a> {
+    nodes: Succesor<'a>,
+}
 
-        while let Some(_node) = node {
-            node = _node.borrow().get_left_child();
-            smallest = _node;
-        }
-        smallest
+impl Entries<'_> {
+    /// Moves the front of the iterator to the newest version of the first key not less than
+    /// `key`, forwards or back. The iterator still keeps to its range.
+    pub fn seek(&mut self, key: &[u8]) {
+        self.nodes.seek(key);
     }
 }
 
-impl Iterator for Succesor {
-    type Item = (String, Option<String>);
+impl Iterator for Entries<'_> {
+    type Item = Entry;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let node_clone = self.node.as_ref();
-        if let Some(node) = node_clone.cloned() {
-            let ret = (node.borrow().key.clone(), node.borrow().value.clone());
-            if let Some(right_child) = node.borrow().get_right_child() {
-                self.node = Some(Self::find_smallest(Rc::clone(&right_child)));
-            } else {
-                let mut child = Rc::clone(&node);
-                let mut parent_op = node.borrow().get_parent();
-                while let Some(parent) = parent_op.as_ref().cloned() {
-                    let parent_right_child = parent.borrow().get_right_child();
-                    match parent_right_child {
-                        Some(right_child) if Rc::ptr_eq(&right_child, &child) => {
-                            parent_op = parent.borrow().get_parent();
-                            child = parent;
-                        }
-                        _ => {
-                            break;
-                        }
-                    };
-                }
-                self.node = parent_op;
-            }
+    fn next(&mut self) -> Option<Entry> {
+        self.nodes.next_node(entry)
+    }
+}
 
-            Some(ret)
-        } else {
-            None
-        }
+impl DoubleEndedIterator for Entries<'_> {
+    fn next_back(&mut self) -> Option<Entry> {
+        self.nodes.next_back_node(entry)
+    }
+}
+
+fn entry(node: &Node) -> Entry {
+    Entry {
+        key: node.key.user_key().to_vec(),
+        sequence: node.key.sequence(),
+        value: node_value(node),
+    }
+}
+
+fn node_value(node: &Node) -> Option<Vec<u8>> {
+    match node.key.value_type() {
+        ValueType::Value => Some(node.value.clone()),
+        ValueType::Deletion => None,
     }
 }
@@ -2,385 +2,937 @@
 // Herlihy, Maurice, Nir Shavit, Victor Luchangco, and Michael Spear.
 // The art of multiprocessor programming. Newnes, 2020.
 mod find_result;
+mod memtable;
 mod node;
 mod tests;
 
+pub use memtable::SkipListMemtable;
+
+use crate::arena::{Arena, ArenaBytes};
+use crate::comparator::{Comparator, Natural};
 use crate::memory_management::hazard_pointers::HazarPointerRecord;
 use crate::util::generate_random_lvl;
 use find_result::FindResult;
-use node::{KeyType, Node};
+use node::{Node, Value, TOP_LEVEL};
+use std::borrow::Borrow;
+use std::cmp;
 use std::collections::HashSet;
+use std::ops::{Bound, RangeBounds};
 use std::ptr;
 use std::sync::atomic::AtomicPtr;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 // Reason for using pointers directly
 // https://rust-unofficial.github.io/too-many-lists/fifth-stacked-borrows.html
 
+// Hazard pointers of a record. A node is only dereferenced while one of them points to it,
+// and only retired once it can no longer be reached from the head at any level.
+const PREDS: usize = 0;
+const SUCCS: usize = PREDS + TOP_LEVEL + 1;
+const CURR: usize = SUCCS + TOP_LEVEL + 1;
+const SUCC: usize = CURR + 1;
+//Node being added, removed or iterated from
+const NODE: usize = SUCC + 1;
+const AUX: usize = NODE + 1;
+const HP_COUNT: usize = AUX + 1;
+
+//Retired nodes a record holds on to before trying to free them
+const MAX_RETIRED: usize = 64;
+
+// Successors are marked once their node is being removed from that level, values once their
+// node is removed from the list.
 #[inline(always)]
-fn get_node<ValueType>(ptr: *mut Node<ValueType>) -> *mut Node<ValueType>
-where
-    ValueType: Clone,
-{
-    (ptr as usize & !0x1) as *mut Node<ValueType>
+fn get_node<T>(ptr: *mut T) -> *mut T {
+    (ptr as usize & !0x1) as *mut T
 }
 
-fn get_marker<ValueType>(ptr: *mut Node<ValueType>) -> bool
-where
-    ValueType: Clone,
-{
+fn get_marker<T>(ptr: *mut T) -> bool {
     (ptr as usize & 0x1) == 0x1
 }
 
-fn add_marker<ValueType>(ptr: *mut Node<ValueType>, marker: bool) -> *mut Node<ValueType>
-where
-    ValueType: Clone,
-{
+fn add_marker<T>(ptr: *mut T, marker: bool) -> *mut T {
     if marker {
-        (ptr as usize | 0x1) as *mut Node<ValueType>
+        (ptr as usize | 0x1) as *mut T
     } else {
-        (ptr as usize & !0x1) as *mut Node<ValueType>
+        (ptr as usize & !0x1) as *mut T
     }
 }
 
-struct SkipList<ValueType>
-where
-    ValueType: Clone,
-{
-    head: *mut Node<ValueType>,
-    tail: *mut Node<ValueType>,
-    hazard_pointer_head: Arc<AtomicPtr<HazarPointerRecord<Node<ValueType>>>>,
+#[inline(always)]
+fn protect<KeyType, ValueType>(
+    hp_record: *mut HazarPointerRecord<Node<KeyType, ValueType>>,
+    slot: usize,
+    node: *mut Node<KeyType, ValueType>,
+) {
+    unsafe { (&(*hp_record).hazard_pointers)[slot].store(node, Ordering::SeqCst) }
+}
+
+// What `SkipList::upsert` did with the value it was given.
+pub(crate) enum Upsert {
+    Inserted,
+    Replaced,
+    Kept,
+}
+
+/// Lock-free skip list from keys to values, with the keys kept in the order of a
+/// [`Comparator`].
+///
+/// Nodes are only as high as their tower and are allocated, together with every value ever
+/// written, from an arena that is released with the list. Keys and values are dropped once a
+/// node is removed and no thread can be looking at it anymore, the memory they took up in the
+/// arena is not reused.
+pub struct SkipList<KeyType, ValueType, C = Natural> {
+    arena: Arena,
+    //The sentinels have no key, nodes are told apart from them by address
+    head: *mut Node<KeyType, ValueType>,
+    tail: *mut Node<KeyType, ValueType>,
+    hazard_pointer_head: Arc<AtomicPtr<HazarPointerRecord<Node<KeyType, ValueType>>>>,
     max_hazard_point_count: Arc<AtomicU32>,
+    len: AtomicUsize,
+    comparator: C,
 }
 
-impl<ValueType> SkipList<ValueType>
-where
-    ValueType: Clone,
-{
-    const MAX_LEVEL: u64 = Node::<ValueType>::TOP_LEVEL as u64;
-    const MAX_HP: u64 = 5;
-
-    pub fn new() -> SkipList<ValueType> {
-        let head = Node::new_sentinel(i128::MIN);
-        let tail = Node::new_sentinel(i128::MAX);
-        let depth;
-        unsafe {
-            depth = (*head).next.len();
-        }
-        for i in 0..depth {
-            unsafe {
-                (*head).next[i] = AtomicPtr::new(tail);
-            }
+impl<KeyType: Ord, ValueType> SkipList<KeyType, ValueType> {
+    pub fn new() -> SkipList<KeyType, ValueType> {
+        SkipList::with_comparator(Natural)
+    }
+}
+
+impl<KeyType, ValueType, C: Comparator<KeyType>> SkipList<KeyType, ValueType, C> {
+    const MAX_LEVEL: u64 = TOP_LEVEL as u64;
+
+    pub fn with_comparator(comparator: C) -> SkipList<KeyType, ValueType, C> {
+        let arena = Arena::new();
+        let head = Node::new_sentinel(&arena, TOP_LEVEL);
+        let tail = Node::new_sentinel(&arena, 0);
+        for level in 0..=TOP_LEVEL {
+            Node::next(head, level).store(tail, Ordering::SeqCst);
         }
-        let max_hp_count = Arc::new(AtomicU32::new(0));
-        let hp_head = Arc::new(AtomicPtr::new(std::ptr::null_mut()));
-        HazarPointerRecord::allocate_hp_record(hp_head.clone(), max_hp_count.clone(), 5);
         SkipList {
+            arena,
             head,
             tail,
-            hazard_pointer_head: hp_head,
-            max_hazard_point_count: max_hp_count,
+            hazard_pointer_head: Arc::new(AtomicPtr::new(ptr::null_mut())),
+            max_hazard_point_count: Arc::new(AtomicU32::new(0)),
+            len: AtomicUsize::new(0),
+            comparator,
         }
     }
 
+    /// Number of keys in the list.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get<Q: ?Sized>(
+        &self,
+        key: &Q,
+        guard: &Guard<'_, KeyType, ValueType, C>,
+    ) -> Option<ValueType>
+    where
+        KeyType: Borrow<Q>,
+        C: Comparator<Q>,
+        ValueType: Clone,
+    {
+        let hp_record = self.record(guard);
+        let result = self.find(key, hp_record);
+        if result.success {
+            unsafe { (*result.succs[0]).current_value() }.cloned()
+        } else {
+            None
+        }
+    }
+
+    /// Adds `key` with `value`, unless the key is in the list already.
     pub fn add(
         &self,
         key: KeyType,
         value: ValueType,
-        hp_record: *mut HazarPointerRecord<Node<ValueType>>,
+        guard: &Guard<'_, KeyType, ValueType, C>,
     ) -> bool {
-        let hp_record = self.enter();
+        matches!(self.upsert(key, value, |_| false, guard), Upsert::Inserted)
+    }
+
+    /// Adds `key` with `value`, or replaces the value of `key` with it. Hands back the value
+    /// it replaced.
+    pub fn insert_or_replace(
+        &self,
+        key: KeyType,
+        value: ValueType,
+        guard: &Guard<'_, KeyType, ValueType, C>,
+    ) -> Option<ValueType>
+    where
+        ValueType: Clone,
+    {
+        let mut replaced = None;
+        self.upsert(
+            key,
+            value,
+            |current| {
+                replaced = Some(current.clone());
+                true
+            },
+            guard,
+        );
+        replaced
+    }
+
+    /// Replaces the value of `key` with `new` if it is `expected`. Otherwise `new` is dropped
+    /// and the value `key` has instead is handed back, `None` if the key is not in the list.
+    pub fn compare_and_swap<Q: ?Sized>(
+        &self,
+        key: &Q,
+        expected: &ValueType,
+        new: ValueType,
+        guard: &Guard<'_, KeyType, ValueType, C>,
+    ) -> Result<(), Option<ValueType>>
+    where
+        KeyType: Borrow<Q>,
+        C: Comparator<Q>,
+        ValueType: PartialEq + Clone,
+    {
+        let mut current = None;
+        let replace = |value: &ValueType| {
+            current = Some(value.clone());
+            value == expected
+        };
+        match self.replace_existing(key, new, replace, guard) {
+            Ok(Upsert::Replaced) => Ok(()),
+            Ok(_) => Err(current),
+            Err(_) => Err(None),
+        }
+    }
+
+    /// Returns the value of `key`, adding `key` with the value `make` returns first if it is
+    /// not in the list. `make` is called at most once, and its value dropped if someone else
+    /// adds the key in the meantime.
+    pub fn get_or_insert_with(
+        &self,
+        key: KeyType,
+        make: impl FnOnce() -> ValueType,
+        guard: &Guard<'_, KeyType, ValueType, C>,
+    ) -> ValueType
+    where
+        ValueType: Clone,
+    {
+        if let Some(value) = self.get(&key, guard) {
+            return value;
+        }
+        let value = make();
+        let mut existing = None;
+        let keep = |current: &ValueType| {
+            existing = Some(current.clone());
+            false
+        };
+        match self.upsert(key, value.clone(), keep, guard) {
+            Upsert::Inserted => value,
+            _ => existing.expect("a kept value is looked at first"),
+        }
+    }
+
+    // Adds `value` under `key`, or replaces the value of `key` with it if `replace` accepts
+    // the current one.
+    pub(crate) fn upsert(
+        &self,
+        key: KeyType,
+        value: ValueType,
+        mut replace: impl FnMut(&ValueType) -> bool,
+        guard: &Guard<'_, KeyType, ValueType, C>,
+    ) -> Upsert {
+        let hp_record = self.record(guard);
+        //Moved from node to node until it is kept or put in
+        let new_value = self.arena.alloc(Value {
+            value,
+            older: ptr::null_mut(),
+        });
+        let mut result = self.find(&key, hp_record);
+        if let Some(upsert) =
+            self.replace_found(&key, &mut result, new_value, &mut replace, hp_record)
+        {
+            return upsert;
+        }
+
+        let new_node = Node::new(&self.arena, key, new_value, Self::random_level());
+        loop {
+            result = match self.link(new_node, result, hp_record) {
+                Ok(()) => return Upsert::Inserted,
+                Err(found) => found,
+            };
+            let key = unsafe { (*new_node).key() };
+            if let Some(upsert) =
+                self.replace_found(key, &mut result, new_value, &mut replace, hp_record)
+            {
+                //Added by someone else in the meantime. The node was never linked, so only its
+                //value was of use.
+                Node::drop_key(new_node);
+                return upsert;
+            }
+        }
+    }
+
+    fn random_level() -> usize {
         let top_level = generate_random_lvl(Self::MAX_LEVEL) as usize;
+        debug_assert!(top_level <= Self::MAX_LEVEL as usize);
+        top_level
+    }
+
+    // Links `new_node` in at the bottom level where `result` says its key goes, then at the
+    // levels above. Hands back what it found instead if a node with the same key is in the way.
+    fn link(
+        &self,
+        new_node: *mut Node<KeyType, ValueType>,
+        mut result: FindResult<KeyType, ValueType>,
+        hp_record: *mut HazarPointerRecord<Node<KeyType, ValueType>>,
+    ) -> Result<(), FindResult<KeyType, ValueType>> {
         let bottom_level = 0;
+        let top_level = unsafe { (*new_node).top_level };
+        //Protected before it is published, anyone may remove it from then on
+        protect(hp_record, NODE, new_node);
         loop {
-            let result = self.find(key, hp_record);
+            for level in bottom_level..=top_level {
+                Node::next(new_node, level).store(result.succs[level], Ordering::SeqCst);
+            }
+            let pred = result.preds[bottom_level];
+            let succ = result.succs[bottom_level];
+            let link = Node::next(pred, bottom_level).compare_exchange(
+                succ,
+                new_node,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+            if link.is_ok() {
+                self.len.fetch_add(1, Ordering::SeqCst);
+                let stale = self.check_link(new_node, succ, bottom_level, hp_record);
+                self.link_levels(new_node, result, stale, hp_record);
+                return Ok(());
+            }
+
+            result = self.find(unsafe { (*new_node).key() }, hp_record);
             if result.success {
-                self.exit(hp_record);
-                return false;
-            } else {
-                debug_assert!(top_level <= Self::MAX_LEVEL as usize);
-                let new_node = Node::new(key, value.clone(), top_level);
-                for level in bottom_level..=top_level {
-                    let succ = result.succs[level];
-                    unsafe {
-                        (*new_node).next[level].store(succ, Ordering::SeqCst);
-                    }
-                }
-                let pred = result.preds[bottom_level];
-                unsafe {
-                    (*hp_record).hazard_pointers[0].store(pred, Ordering::SeqCst);
-                }
-                let succ = result.succs[bottom_level];
-                unsafe {
-                    (*hp_record).hazard_pointers[1].store(succ, Ordering::SeqCst);
+                return Err(result);
+            }
+        }
+    }
+
+    // Replaces the value of `key` with `value` if `replace` accepts the current one. Hands
+    // `value` back if there is no such key.
+    pub(crate) fn replace_existing<Q: ?Sized>(
+        &self,
+        key: &Q,
+        value: ValueType,
+        mut replace: impl FnMut(&ValueType) -> bool,
+        guard: &Guard<'_, KeyType, ValueType, C>,
+    ) -> Result<Upsert, ValueType>
+    where
+        KeyType: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let hp_record = self.record(guard);
+        let mut result = self.find(key, hp_record);
+        if !result.success {
+            return Err(value);
+        }
+        let new_value = self.arena.alloc(Value {
+            value,
+            older: ptr::null_mut(),
+        });
+        match self.replace_found(key, &mut result, new_value, &mut replace, hp_record) {
+            Some(upsert) => Ok(upsert),
+            //Removed before it could be replaced. The copy in the arena is left behind.
+            None => Err(unsafe { ptr::read(&(*new_value).value) }),
+        }
+    }
+
+    // Replaces the value of the node `result` found for `key` as `replace_value` does, for as
+    // long as it finds one. Returns `None` once it does not, leaving in `result` where the key
+    // would go.
+    fn replace_found<Q: ?Sized>(
+        &self,
+        key: &Q,
+        result: &mut FindResult<KeyType, ValueType>,
+        new_value: *mut Value<ValueType>,
+        replace: &mut impl FnMut(&ValueType) -> bool,
+        hp_record: *mut HazarPointerRecord<Node<KeyType, ValueType>>,
+    ) -> Option<Upsert>
+    where
+        KeyType: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        while result.success {
+            if let Some(upsert) = self.replace_value(result.succs[0], new_value, &mut *replace) {
+                return Some(upsert);
+            }
+            *result = self.find(key, hp_record);
+        }
+        None
+    }
+
+    // Puts `new_value`, which is not linked anywhere yet, in front of the value of `node` if
+    // `replace` accepts the current one. Returns `None` if the node is removed first, once
+    // it is marked at every level so that it is no longer found.
+    fn replace_value(
+        &self,
+        node: *mut Node<KeyType, ValueType>,
+        new_value: *mut Value<ValueType>,
+        mut replace: impl FnMut(&ValueType) -> bool,
+    ) -> Option<Upsert> {
+        loop {
+            let current = unsafe { (*node).value.load(Ordering::SeqCst) };
+            if get_marker(current) {
+                // The value may be linked into another node after this, so it must not keep
+                // pointing into the chain of the removed one.
+                unsafe { (*new_value).older = ptr::null_mut() };
+                self.mark_levels(node);
+                return None;
+            }
+            if !replace(unsafe { &(*current).value }) {
+                unsafe { ptr::drop_in_place(new_value) };
+                return Some(Upsert::Kept);
+            }
+            unsafe {
+                (*new_value).older = current;
+                if (*node)
+                    .value
+                    .compare_exchange(current, new_value, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    return Some(Upsert::Replaced);
                 }
-                unsafe {
-                    if (*pred).next[bottom_level]
-                        .compare_exchange(succ, new_node, Ordering::SeqCst, Ordering::SeqCst)
-                        .is_err()
-                    {
-                        println!("Failed to replace node");
-                        continue;
+            }
+        }
+    }
+
+    // Links a node that was just added at the bottom level into the levels above it. `result`
+    // is what the node was added with, `stale` tells whether the nodes in it are no longer
+    // protected.
+    fn link_levels(
+        &self,
+        new_node: *mut Node<KeyType, ValueType>,
+        result: FindResult<KeyType, ValueType>,
+        stale: bool,
+        hp_record: *mut HazarPointerRecord<Node<KeyType, ValueType>>,
+    ) {
+        let (mut result, mut stale) = (result, stale);
+        let top_level = unsafe { (*new_node).top_level };
+        for level in 1..=top_level {
+            loop {
+                if stale {
+                    result = self.find(unsafe { (*new_node).key() }, hp_record);
+                    if result.succs[0] != new_node {
+                        //Removed in the meantime
+                        return;
                     }
                 }
-                let mut result = result;
-                for level in bottom_level + 1..=top_level {
-                    loop {
-                        let pred = result.preds[level];
-                        unsafe {
-                            (*hp_record).hazard_pointers[2].store(pred, Ordering::SeqCst);
-                        }
-                        let succ = result.succs[level];
-                        unsafe {
-                            (*hp_record).hazard_pointers[3].store(succ, Ordering::SeqCst);
-                        }
-                        unsafe {
-                            if (*pred).next[level]
-                                .compare_exchange(
-                                    succ,
-                                    new_node,
-                                    Ordering::SeqCst,
-                                    Ordering::SeqCst,
-                                )
-                                .is_ok()
-                            {
-                                break;
-                            }
-                        }
-                        result = self.find(key, hp_record);
-                    }
+                let pred = result.preds[level];
+                let succ = result.succs[level];
+                let next = Node::next(new_node, level);
+                let current = next.load(Ordering::SeqCst);
+                if get_marker(current)
+                    || (current != succ
+                        && next
+                            .compare_exchange(current, succ, Ordering::SeqCst, Ordering::SeqCst)
+                            .is_err())
+                {
+                    //Being removed, linking it any further would only make it reachable again
+                    return;
+                }
+                let link = Node::next(pred, level).compare_exchange(
+                    succ,
+                    new_node,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                );
+                stale = true;
+                if link.is_ok() {
+                    stale = self.check_link(new_node, succ, level, hp_record);
+                    break;
                 }
-                self.exit(hp_record);
-                return true;
             }
         }
     }
 
-    pub fn remove(
+    // Makes sure that linking `node` in front of `succ` at `level` did not make either of them
+    // reachable again after whoever is removing it unlinked it. Returns whether the nodes that
+    // were protected for adding `node` are no longer.
+    fn check_link(
         &self,
-        key: KeyType,
-        hp_record: *mut HazarPointerRecord<Node<ValueType>>,
+        node: *mut Node<KeyType, ValueType>,
+        succ: *mut Node<KeyType, ValueType>,
+        level: usize,
+        hp_record: *mut HazarPointerRecord<Node<KeyType, ValueType>>,
     ) -> bool {
-        let hp_record = self.enter();
+        let mut stale = false;
+        if succ != self.tail && get_marker(Node::next(succ, level).load(Ordering::SeqCst)) {
+            protect(hp_record, AUX, succ);
+            self.unlink(succ, hp_record);
+            stale = true;
+        }
+        if get_marker(Node::next(node, level).load(Ordering::SeqCst)) {
+            self.unlink(node, hp_record);
+            stale = true;
+        }
+        stale
+    }
+
+    pub fn remove<Q: ?Sized>(&self, key: &Q, guard: &Guard<'_, KeyType, ValueType, C>) -> bool
+    where
+        KeyType: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let hp_record = self.record(guard);
         const BOTTOM_LEVEL: usize = 0;
-        let mut succ;
-        'retry: loop {
-            let result = self.find(key, hp_record);
-            if !result.success {
-                self.exit(hp_record);
-                return false;
-            } else {
-                let node_to_remove = result.succs[BOTTOM_LEVEL];
-                unsafe {
-                    (*hp_record).hazard_pointers[0].store(node_to_remove, Ordering::SeqCst);
-                }
-                let height;
-                unsafe {
-                    height = (*node_to_remove).top_level;
-                }
-                for level in (BOTTOM_LEVEL + 1..=height).rev() {
-                    let mut marked;
-                    unsafe {
-                        let composite = (*node_to_remove).next[level].load(Ordering::SeqCst);
-                        succ = get_node(composite);
-                        (*hp_record).hazard_pointers[1].store(succ, Ordering::SeqCst);
-                        marked = get_marker(composite);
-                        // Keep trying to mark successor to predecessor until it's not marked
-                        while !marked {
-                            let _ = (*node_to_remove).next[level].compare_exchange(
-                                succ,
-                                add_marker(succ, true),
-                                Ordering::SeqCst,
-                                Ordering::SeqCst,
-                            );
-                            let composite = (*node_to_remove).next[level].load(Ordering::SeqCst);
-                            succ = get_node(composite);
-                            (*hp_record).hazard_pointers[1].store(succ, Ordering::SeqCst);
-                            marked = get_marker(composite);
-                        }
-                    }
-                }
-                let mut marked;
-                unsafe {
-                    let composite = (*node_to_remove).next[BOTTOM_LEVEL].load(Ordering::SeqCst);
-                    succ = get_node(composite);
-                    (*hp_record).hazard_pointers[1].store(succ, Ordering::SeqCst);
-                    marked = get_marker(composite);
-                }
-                loop {
-                    let exchange_result;
-                    unsafe {
-                        exchange_result = (*node_to_remove).next[BOTTOM_LEVEL].compare_exchange(
-                            succ,
-                            add_marker(succ, true),
-                            Ordering::SeqCst,
-                            Ordering::SeqCst,
-                        );
-                        let composite =
-                            (*result.succs[BOTTOM_LEVEL]).next[BOTTOM_LEVEL].load(Ordering::SeqCst);
-                        succ = get_node(composite);
-                        (*hp_record).hazard_pointers[2].store(succ, Ordering::SeqCst);
-                        marked = get_marker(composite);
-                    }
-                    // If bottom level node was marked; find it and return true else if
-                    // failed and someone else marked it then return false
-                    if exchange_result.is_ok() {
-                        let _ = self.find(key, hp_record);
-                        self.exit(hp_record);
-                        return true;
-                    } else if marked {
-                        self.exit(hp_record);
-                        return false;
-                    }
-                }
+        let result = self.find(key, hp_record);
+        if !result.success {
+            return false;
+        }
+
+        let node_to_remove = result.succs[BOTTOM_LEVEL];
+        protect(hp_record, NODE, node_to_remove);
+        // Whoever marks the value removed the node, so that it cannot be replaced after
+        let value = unsafe { &(*node_to_remove).value };
+        let mut current = value.load(Ordering::SeqCst);
+        let removed = loop {
+            if get_marker(current) {
+                break false;
+            }
+            match value.compare_exchange(
+                current,
+                add_marker(current, true),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => break true,
+                Err(value) => current = value,
+            }
+        };
+        if removed {
+            self.mark_levels(node_to_remove);
+            self.len.fetch_sub(1, Ordering::SeqCst);
+            self.unlink(node_to_remove, hp_record);
+            HazarPointerRecord::retire_node(
+                self.hazard_pointer_head.load(Ordering::SeqCst),
+                hp_record,
+                node_to_remove,
+                MAX_RETIRED,
+                Node::reclaim,
+            );
+        }
+        removed
+    }
+
+    // Marks the successors of a removed node top-down. Anyone who comes across its marked value
+    // may do so.
+    fn mark_levels(&self, node: *mut Node<KeyType, ValueType>) {
+        let top_level = unsafe { (*node).top_level };
+        for level in (0..=top_level).rev() {
+            // Keep trying to mark successor to predecessor until it's marked
+            let mut succ = Node::next(node, level).load(Ordering::SeqCst);
+            while !get_marker(succ) {
+                let _ = Node::next(node, level).compare_exchange(
+                    succ,
+                    add_marker(succ, true),
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                );
+                succ = Node::next(node, level).load(Ordering::SeqCst);
             }
         }
     }
 
-    fn find(
+    fn find<Q: ?Sized>(
         &self,
-        key: KeyType,
-        hazard_pointer_record: *mut HazarPointerRecord<Node<ValueType>>,
-    ) -> FindResult<ValueType> {
-        let hazard_pointer_record = self.enter();
-        const BOTTOM_LEVEL: u64 = 0;
-        let top_level = Self::MAX_LEVEL;
-        let mut snip;
-        let mut pred: *mut Node<ValueType>;
-        let mut marked: bool;
-        let mut curr = ptr::null_mut();
-        let mut succ;
-        let mut preds = vec![std::ptr::null_mut(); top_level as usize + 1];
-        let mut succs = vec![std::ptr::null_mut(); top_level as usize + 1];
-        let mut free_collection = HashSet::new();
+        key: &Q,
+        hazard_pointer_record: *mut HazarPointerRecord<Node<KeyType, ValueType>>,
+    ) -> FindResult<KeyType, ValueType>
+    where
+        KeyType: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let mut result = self.search(hazard_pointer_record, |curr_key| {
+            self.compare(curr_key, key) == cmp::Ordering::Less
+        });
+        let found = result.succs[0];
+        result.success = found != self.tail
+            && unsafe { self.compare((*found).key(), key) == cmp::Ordering::Equal };
+        result
+    }
+
+    // Unlinks `node`, which is marked at every level, from every level it can still be reached
+    // at. `node` must be protected.
+    fn unlink(
+        &self,
+        node: *mut Node<KeyType, ValueType>,
+        hazard_pointer_record: *mut HazarPointerRecord<Node<KeyType, ValueType>>,
+    ) {
+        let key = unsafe { (*node).key() };
+        //Nodes with the same key come before it
+        self.search(hazard_pointer_record, |curr_key| {
+            self.compare(curr_key, key) != cmp::Ordering::Greater
+        });
+    }
+
+    fn compare<Q: ?Sized>(&self, node_key: &KeyType, key: &Q) -> cmp::Ordering
+    where
+        KeyType: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        self.comparator.compare(node_key.borrow(), key)
+    }
+
+    // Finds at every level the last node whose key `before` holds for and the node after it,
+    // unlinking marked nodes on the way. Both are left protected by `hazard_pointer_record`,
+    // `success` is left for the caller to fill in.
+    fn search(
+        &self,
+        hazard_pointer_record: *mut HazarPointerRecord<Node<KeyType, ValueType>>,
+        before: impl Fn(&KeyType) -> bool,
+    ) -> FindResult<KeyType, ValueType> {
+        let mut preds = vec![ptr::null_mut(); TOP_LEVEL + 1];
+        let mut succs = vec![ptr::null_mut(); TOP_LEVEL + 1];
         'retry: loop {
-            pred = self.head;
-            unsafe {
-                (*hazard_pointer_record).hazard_pointers[0].store(pred, Ordering::SeqCst);
-            }
-            for lvl in (BOTTOM_LEVEL..=top_level).rev() {
-                unsafe {
-                    let composite = (*pred).next[lvl as usize].load(Ordering::SeqCst);
-                    curr = get_node(composite);
-                    (*hazard_pointer_record).hazard_pointers[1].store(curr, Ordering::SeqCst);
+            let mut pred = self.head;
+            for lvl in (0..=TOP_LEVEL).rev() {
+                protect(hazard_pointer_record, PREDS + lvl, pred);
+                let mut curr = Node::next(pred, lvl).load(Ordering::SeqCst);
+                //A marked pred is being removed and what it points to may be gone already
+                if get_marker(curr) {
+                    continue 'retry;
+                }
+                protect(hazard_pointer_record, CURR, curr);
+                if Node::next(pred, lvl).load(Ordering::SeqCst) != curr {
+                    continue 'retry;
                 }
-                loop {
-                    unsafe {
-                        let composite = (*curr).next[lvl as usize].load(Ordering::SeqCst);
-                        succ = get_node(composite);
-                        (*hazard_pointer_record).hazard_pointers[2].store(succ, Ordering::SeqCst);
-                        marked = get_marker(composite);
+
+                while curr != self.tail {
+                    let composite = Node::next(curr, lvl).load(Ordering::SeqCst);
+                    let succ = get_node(composite);
+                    protect(hazard_pointer_record, SUCC, succ);
+                    if Node::next(curr, lvl).load(Ordering::SeqCst) != composite {
+                        continue 'retry;
                     }
-                    while marked {
-                        unsafe {
-                            snip = (*pred).next[lvl as usize].compare_exchange(
-                                curr,
-                                succ,
-                                Ordering::SeqCst,
-                                Ordering::SeqCst,
-                            )
-                        };
+
+                    if get_marker(composite) {
+                        //Unlinking curr also proves it was still linked, so succ was too
+                        let snip = Node::next(pred, lvl).compare_exchange(
+                            curr,
+                            succ,
+                            Ordering::SeqCst,
+                            Ordering::SeqCst,
+                        );
                         if snip.is_err() {
                             continue 'retry;
                         }
-                        if lvl == BOTTOM_LEVEL {
-                            free_collection.insert(curr);
-                        }
-                        unsafe {
-                            let composite = (*pred).next[lvl as usize].load(Ordering::SeqCst);
-                            debug_assert!(composite != std::ptr::null_mut());
-                            curr = get_node(composite);
-                            (*hazard_pointer_record).hazard_pointers[1]
-                                .store(curr, Ordering::SeqCst);
-                            let composite = (*curr).next[lvl as usize].load(Ordering::SeqCst);
-                            marked = get_marker(composite);
-                            succ = get_node(composite);
-                            (*hazard_pointer_record).hazard_pointers[2]
-                                .store(succ, Ordering::SeqCst);
-                        }
-                    }
-                    let curr_key = unsafe { (*curr).key };
-                    if curr_key < key {
+                    } else if before(unsafe { (*curr).key() }) {
                         pred = curr;
-                        curr = succ;
+                        protect(hazard_pointer_record, PREDS + lvl, pred);
                     } else {
                         break;
                     }
+                    curr = succ;
+                    protect(hazard_pointer_record, CURR, curr);
                 }
-                preds[lvl as usize] = pred;
-                succs[lvl as usize] = curr;
+                preds[lvl] = pred;
+                succs[lvl] = curr;
+                protect(hazard_pointer_record, SUCCS + lvl, curr);
             }
-            for node in free_collection.into_iter() {
-                HazarPointerRecord::retire_node(
-                    self.hazard_pointer_head.load(Ordering::SeqCst),
-                    hazard_pointer_record,
-                    node,
-                    1,
-                );
-            }
-            let success = unsafe { (*curr).key == key };
-            self.exit(hazard_pointer_record);
             return FindResult {
-                success,
+                success: false,
                 preds,
                 succs,
             };
         }
     }
 
-    fn enter(&self) -> *mut HazarPointerRecord<Node<ValueType>> {
-        HazarPointerRecord::allocate_hp_record(
-            self.hazard_pointer_head.clone(),
-            self.max_hazard_point_count.clone(),
-            Self::MAX_HP as u32,
-        )
+    /// Iterates over all keys and values in key order.
+    pub fn iter(&self) -> Iter<'_, KeyType, ValueType, C>
+    where
+        KeyType: Clone,
+    {
+        self.range(..)
     }
 
-    fn exit(&self, hp_record: *mut HazarPointerRecord<Node<ValueType>>) {
-        HazarPointerRecord::retire_hp_record(hp_record);
+    /// Iterates over the keys inside `range` and their values in key order, so
+    /// `list.range::<str, _>(("b", "d"))` works for `String` keys as it does for a `BTreeMap`.
+    pub fn range<Q, R>(&self, range: R) -> Iter<'_, KeyType, ValueType, C, Q>
+    where
+        Q: ?Sized + ToOwned,
+        KeyType: Borrow<Q>,
+        C: Comparator<Q>,
+        R: RangeBounds<Q>,
+    {
+        let mut iter = Iter {
+            list: self,
+            guard: self.guard(),
+            node: self.head,
+            start: range.start_bound().map(Q::to_owned),
+            end: range.end_bound().map(Q::to_owned),
+        };
+        iter.seek_to(range.start_bound());
+        iter
     }
 }
 
-impl<ValueType> Drop for SkipList<ValueType>
-where
-    ValueType: Clone,
-{
-    fn drop(&mut self) {
-        fn drop_hp_records<ValueType>(head: *mut HazarPointerRecord<ValueType>) {
-            let mut record = head;
-            while (record != std::ptr::null_mut()) {
-                unsafe {
-                    let temp = Box::from_raw(record);
-                    record = temp.next.load(Ordering::SeqCst);
-                }
-            }
+impl<C: Comparator<ArenaBytes> + Comparator<[u8]>> SkipList<ArenaBytes, ArenaBytes, C> {
+    // Adds `key` with `value`, unless the key is in the list already, copying both into the
+    // block the node is allocated in. Returns the size of the block, which stays in the arena
+    // unused if someone else adds the key first.
+    pub(crate) fn add_inline(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        guard: &Guard<'_, ArenaBytes, ArenaBytes, C>,
+    ) -> Option<usize> {
+        let hp_record = self.record(guard);
+        let result = self.find(key, hp_record);
+        if result.success {
+            return None;
         }
+        let (new_node, size) = Node::new_inline(&self.arena, key, value, Self::random_level());
+        self.link(new_node, result, hp_record).ok().map(|()| size)
+    }
+}
+
+impl<KeyType, ValueType, C> SkipList<KeyType, ValueType, C> {
+    /// Takes a guard for the calling thread to work on the list with.
+    pub fn guard(&self) -> Guard<'_, KeyType, ValueType, C> {
+        Guard {
+            list: self,
+            hp_record: HazarPointerRecord::allocate_hp_record(
+                self.hazard_pointer_head.clone(),
+                self.max_hazard_point_count.clone(),
+                HP_COUNT as u32,
+            ),
+        }
+    }
 
-        drop_hp_records(self.hazard_pointer_head.load(Ordering::SeqCst));
+    fn record(
+        &self,
+        guard: &Guard<'_, KeyType, ValueType, C>,
+    ) -> *mut HazarPointerRecord<Node<KeyType, ValueType>> {
+        //Its hazard pointers would not be looked at before freeing a node of this list
+        assert!(
+            ptr::eq(guard.list, self),
+            "guard was taken from another skip list"
+        );
+        guard.hp_record
+    }
+}
 
-        let mut curr = self.head;
+impl<KeyType: Ord, ValueType> Default for SkipList<KeyType, ValueType> {
+    fn default() -> SkipList<KeyType, ValueType> {
+        SkipList::new()
+    }
+}
+
+impl<KeyType, ValueType, C> Drop for SkipList<KeyType, ValueType, C> {
+    fn drop(&mut self) {
         let mut free_collection = HashSet::new();
-        for lvl in (0..=Self::MAX_LEVEL).rev() {
+
+        //Nodes that were retired but not freed yet go with the records
+        let mut record = self.hazard_pointer_head.load(Ordering::SeqCst);
+        while !record.is_null() {
+            let temp = unsafe { Box::from_raw(record) };
+            free_collection.extend(temp.r_list.iter().copied());
+            record = temp.next.load(Ordering::SeqCst);
+        }
+
+        for lvl in (0..=TOP_LEVEL).rev() {
+            let mut curr = self.head;
             while curr != self.tail {
-                let next;
-                unsafe {
-                    next = (*curr).next[lvl as usize].load(Ordering::SeqCst);
-                }
                 free_collection.insert(curr);
-                curr = get_node(next);
+                curr = get_node(Node::next(curr, lvl).load(Ordering::SeqCst));
             }
-            free_collection.insert(curr);
-            curr = self.head;
         }
-        free_collection.insert(self.head);
+        free_collection.insert(self.tail);
+        //The arena takes their memory along with it
         for node in free_collection.into_iter() {
-            unsafe {
-                let _ = drop(Box::from_raw(node));
+            Node::reclaim(node);
+        }
+    }
+}
+
+unsafe impl<K: Send + Sync, V: Send + Sync, C: Send + Sync> Send for SkipList<K, V, C> {}
+unsafe impl<K: Send + Sync, V: Send + Sync, C: Send + Sync> Sync for SkipList<K, V, C> {}
+
+/// Lets the thread that took it work on a [`SkipList`]. Nodes the thread is looking at are
+/// kept from being freed through it, so it cannot be handed to another thread, and it only
+/// works with the list it was taken from. Gives its hazard pointers back to the list when
+/// dropped.
+///
+/// Taking a guard is cheap once the list has a free record, keeping one around for many
+/// operations is cheaper still.
+pub struct Guard<'a, KeyType, ValueType, C = Natural> {
+    list: &'a SkipList<KeyType, ValueType, C>,
+    hp_record: *mut HazarPointerRecord<Node<KeyType, ValueType>>,
+}
+
+impl<KeyType, ValueType, C> Drop for Guard<'_, KeyType, ValueType, C> {
+    fn drop(&mut self) {
+        HazarPointerRecord::retire_hp_record(self.hp_record);
+    }
+}
+
+/// Iterator over the keys and values of a [`SkipList`], handing out clones of them. Keys added
+/// and removed while iterating may or may not show up, the keys that do come out in order and
+/// each at most once.
+///
+/// Iterating from the back looks every key up from the top of the list again, so it is slower
+/// than iterating from the front. `Q` is the form of the key the range was given in.
+pub struct Iter<'a, KeyType, ValueType, C = Natural, Q: ?Sized + ToOwned = KeyType> {
+    list: &'a SkipList<KeyType, ValueType, C>,
+    //Keeps the node the front is at from being freed
+    guard: Guard<'a, KeyType, ValueType, C>,
+    //Last node handed out from the front, or the one before the first to hand out. The tail
+    //once the iterator is done.
+    node: *mut Node<KeyType, ValueType>,
+    //Keys added in front of the range after `node` was found come after it all the same
+    start: Bound<Q::Owned>,
+    //End of the range, moved down past every key handed out from the back
+    end: Bound<Q::Owned>,
+}
+
+impl<KeyType, ValueType, C, Q> Iter<'_, KeyType, ValueType, C, Q>
+where
+    KeyType: Borrow<Q>,
+    C: Comparator<KeyType> + Comparator<Q>,
+    Q: ?Sized + ToOwned,
+{
+    /// Moves the front of the iterator to the first key not less than `key`, forwards or back.
+    /// The iterator still keeps to its range.
+    pub fn seek<S: ?Sized>(&mut self, key: &S)
+    where
+        KeyType: Borrow<S>,
+        C: Comparator<S>,
+    {
+        self.seek_to(Bound::Included(key));
+    }
+
+    fn after_start(&self, key: &KeyType) -> bool {
+        match &self.start {
+            Bound::Included(start) => self.list.compare(key, start.borrow()) != cmp::Ordering::Less,
+            Bound::Excluded(start) => {
+                self.list.compare(key, start.borrow()) == cmp::Ordering::Greater
+            }
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn before_end(&self, key: &KeyType) -> bool {
+        match &self.end {
+            Bound::Included(end) => self.list.compare(key, end.borrow()) != cmp::Ordering::Greater,
+            Bound::Excluded(end) => self.list.compare(key, end.borrow()) == cmp::Ordering::Less,
+            Bound::Unbounded => true,
+        }
+    }
+
+    // Moves the front to just before the first key inside `start`.
+    fn seek_to<S: ?Sized>(&mut self, start: Bound<&S>)
+    where
+        KeyType: Borrow<S>,
+        C: Comparator<S>,
+    {
+        self.node = match start {
+            Bound::Unbounded => self.list.head,
+            Bound::Included(key) => self.list.find(key, self.guard.hp_record).preds[0],
+            Bound::Excluded(key) => {
+                let result = self.list.find(key, self.guard.hp_record);
+                if result.success {
+                    result.succs[0]
+                } else {
+                    result.preds[0]
+                }
+            }
+        };
+        protect(self.guard.hp_record, NODE, self.node);
+    }
+}
+
+impl<KeyType, ValueType, C, Q> Iterator for Iter<'_, KeyType, ValueType, C, Q>
+where
+    KeyType: Clone + Borrow<Q>,
+    ValueType: Clone,
+    C: Comparator<KeyType> + Comparator<Q>,
+    Q: ?Sized + ToOwned,
+{
+    type Item = (KeyType, ValueType);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.node == self.list.tail {
+                return None;
+            }
+            let composite = Node::next(self.node, 0).load(Ordering::SeqCst);
+            if get_marker(composite) {
+                //Removed, so what it points to may be gone. Look for the key after it instead.
+                let key = unsafe { (*self.node).key() };
+                self.seek_to::<KeyType>(Bound::Excluded(key));
+                continue;
+            }
+            let succ = get_node(composite);
+            protect(self.guard.hp_record, SUCC, succ);
+            if Node::next(self.node, 0).load(Ordering::SeqCst) != composite {
+                continue;
+            }
+
+            self.node = succ;
+            protect(self.guard.hp_record, NODE, succ);
+            if succ == self.list.tail {
+                return None;
+            }
+            let node = unsafe { &*succ };
+            if get_marker(Node::next(succ, 0).load(Ordering::SeqCst)) {
+                continue;
+            }
+            if !self.before_end(node.key()) {
+                self.node = self.list.tail;
+                return None;
+            }
+            if !self.after_start(node.key()) {
+                continue;
+            }
+            if let Some(value) = node.current_value() {
+                return Some((node.key().clone(), value.clone()));
             }
         }
     }
 }
 
-unsafe impl<V> Send for SkipList<V> where V: Clone {}
-unsafe impl<V> Sync for SkipList<V> where V: Clone {}
+impl<KeyType, ValueType, C, Q> DoubleEndedIterator for Iter<'_, KeyType, ValueType, C, Q>
+where
+    KeyType: Clone + Borrow<Q>,
+    ValueType: Clone,
+    C: Comparator<KeyType> + Comparator<Q>,
+    Q: ?Sized + ToOwned,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let list = self.list;
+        loop {
+            if self.node == list.tail {
+                return None;
+            }
+            let result = list.search(self.guard.hp_record, |key| self.before_end(key));
+            //Last node inside the end, protected until the next search
+            let last = result.preds[0];
+            let met_front = last == list.head
+                || !self.after_start(unsafe { (*last).key() })
+                || (self.node != list.head
+                    && unsafe { list.compare::<KeyType>((*last).key(), (*self.node).key()) }
+                        != cmp::Ordering::Greater);
+            if met_front {
+                self.node = list.tail;
+                return None;
+            }
+
+            let node = unsafe { &*last };
+            self.end = Bound::Excluded(node.key().borrow().to_owned());
+            if let Some(value) = node.current_value() {
+                return Some((node.key().clone(), value.clone()));
+            }
+        }
+    }
+}
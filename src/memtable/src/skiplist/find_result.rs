@@ -1,10 +1,7 @@
 ﻿use crate::skiplist::node::Node;
 
-pub struct FindResult<ValueType>
-where
-    ValueType: Clone,
-{
+pub struct FindResult<KeyType, ValueType> {
     pub success: bool,
-    pub preds: Vec<*mut Node<ValueType>>,
-    pub succs: Vec<*mut Node<ValueType>>,
+    pub preds: Vec<*mut Node<KeyType, ValueType>>,
+    pub succs: Vec<*mut Node<KeyType, ValueType>>,
 }
@@ -0,0 +1,128 @@
+use super::{Iter, SkipList};
+use crate::arena::ArenaBytes;
+use crate::key::{self, version_bounds, InternalKeyOrder};
+use crate::{Entry, InternalKey, Lookup, Memtable, SequenceNumber, ValueType};
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Memtable over the lock-free [`SkipList`](super::SkipList). Any number of threads can look
+/// up, write and iterate over it at once without blocking each other.
+///
+/// Every write is a node of its own, keyed by its encoded [`InternalKey`]. A node is a single
+/// block of the arena of the list, sized for its height, with the key and value bytes inline
+/// after its tower. [`Memtable::approximate_memory_usage`] is exactly the size of the blocks
+/// of the entries, and all of them are given back at once when the memtable is dropped.
+pub struct SkipListMemtable {
+    //Keys and values point into the nodes themselves, values of deletions are empty
+    list: SkipList<ArenaBytes, ArenaBytes, InternalKeyOrder>,
+    memory: AtomicUsize,
+}
+
+impl SkipListMemtable {
+    pub fn new() -> SkipListMemtable {
+        SkipListMemtable {
+            list: SkipList::with_comparator(InternalKeyOrder),
+            memory: AtomicUsize::new(0),
+        }
+    }
+
+    fn write(&self, key: InternalKey, val: &[u8]) {
+        //Nothing is added for the same write made again
+        let guard = self.list.guard();
+        if let Some(size) = self.list.add_inline(&key.encode(), val, &guard) {
+            self.memory.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for SkipListMemtable {
+    fn default() -> SkipListMemtable {
+        SkipListMemtable::new()
+    }
+}
+
+impl Memtable for SkipListMemtable {
+    type Iter<'a> = Entries<'a>;
+
+    fn lookup(&self, key: &[u8], snapshot: SequenceNumber) -> Lookup {
+        let lookup = InternalKey::lookup(key, snapshot).encode();
+        let newest = self
+            .list
+            .range::<[u8], _>((Bound::Included(&lookup[..]), Bound::Unbounded))
+            .next();
+        let Some((newest, val)) = newest else {
+            return Lookup::NotFound;
+        };
+        match key::decode(newest.as_slice()) {
+            (user_key, _, _) if user_key != key => Lookup::NotFound,
+            (_, _, ValueType::Value) => Lookup::Found(val.as_slice().to_vec()),
+            (_, _, ValueType::Deletion) => Lookup::Deleted,
+        }
+    }
+
+    fn put(&self, key: &[u8], val: &[u8], seq: SequenceNumber) {
+        self.write(InternalKey::new(key, seq, ValueType::Value), val);
+    }
+
+    fn delete(&self, key: &[u8], seq: SequenceNumber) {
+        self.write(InternalKey::new(key, seq, ValueType::Deletion), &[]);
+    }
+
+    fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    fn approximate_memory_usage(&self) -> usize {
+        self.memory.load(Ordering::Relaxed)
+    }
+
+    fn iter(&self) -> Entries<'_> {
+        self.range(..)
+    }
+
+    fn range<'k, R: RangeBounds<&'k [u8]>>(&self, range: R) -> Entries<'_> {
+        let (start, end) = version_bounds(range.start_bound().cloned(), range.end_bound().cloned());
+        let start = start.map(|start| start.encode());
+        let end = end.map(|end| end.encode());
+        Entries {
+            iter: self.list.range::<[u8], _>((
+                start.as_ref().map(Vec::as_slice),
+                end.as_ref().map(Vec::as_slice),
+            )),
+        }
+    }
+}
+
+/// Entries of a [`SkipListMemtable`] in the order of [`Memtable::iter`], from either end.
+/// Writes made while iterating may or may not show up.
+pub struct Entries<'a> {
+    iter: Iter<'a, ArenaBytes, ArenaBytes, InternalKeyOrder, [u8]>,
+}
+
+impl Iterator for Entries<'_> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        let (key, val) = self.iter.next()?;
+        Some(entry(key, val))
+    }
+}
+
+impl DoubleEndedIterator for Entries<'_> {
+    fn next_back(&mut self) -> Option<Entry> {
+        let (key, val) = self.iter.next_back()?;
+        Some(entry(key, val))
+    }
+}
+
+fn entry(key: ArenaBytes, val: ArenaBytes) -> Entry {
+    let (user_key, sequence, value_type) = key::decode(key.as_slice());
+    Entry {
+        key: user_key.to_vec(),
+        sequence,
+        value: match value_type {
+            ValueType::Value => Some(val.as_slice().to_vec()),
+            ValueType::Deletion => None,
+        },
+    }
+}
@@ -1,46 +1,170 @@
-﻿use std::sync::Arc;
-use std::sync::atomic::AtomicPtr;
-
-pub type KeyType = i128;
-
-#[repr(align(2))]
-#[derive(Debug)]
-pub(crate) struct Node<ValueType>
-where
-    ValueType: Clone,
-{
-    pub key: KeyType,
-    pub value: Option<ValueType>,
+﻿use super::{get_marker, get_node};
+use crate::arena::{Arena, ArenaBytes};
+use std::alloc::Layout;
+use std::mem::MaybeUninit;
+use std::ptr::{self, addr_of_mut};
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+pub const TOP_LEVEL: usize = 31;
+
+// A value of a node. Replacing it leaves the old one reachable from the new one, so that
+// readers of the old one are safe for as long as they protect the node.
+pub struct Value<ValueType> {
+    pub value: ValueType,
+    pub older: *mut Value<ValueType>,
+}
+
+// Nodes and their values are allocated from the arena of their list. Only as much of `next` as
+// the node is high is allocated, so it must not be accessed past `top_level`. Nodes of byte keys
+// and values can have both, and their value, in the same block as their tower.
+#[repr(C, align(2))]
+pub struct Node<KeyType, ValueType> {
+    //Left uninitialised for the sentinels, which are told apart by the list itself
+    key: MaybeUninit<KeyType>,
+    //Null for the sentinels, marked once the node is removed
+    pub value: AtomicPtr<Value<ValueType>>,
     pub top_level: usize,
-    pub next: [AtomicPtr<Node<ValueType>>; 32],
+    next: [AtomicPtr<Node<KeyType, ValueType>>; 0],
 }
 
-impl<ValueType> Node<ValueType>
-where
-    ValueType: Clone,
-{
-    pub const TOP_LEVEL: usize = 31;
-    pub fn new_sentinel(key: KeyType) -> *mut Node<ValueType> {
-        let vec = (0..Node::<ValueType>::TOP_LEVEL + 1)
-            .map(|i| AtomicPtr::new(std::ptr::null_mut()))
-            .collect::<Vec<_>>();
-        Box::into_raw(Box::new(Node {
-            key,
-            value: None,
-            top_level: Node::<ValueType>::TOP_LEVEL,
-            next: vec.try_into().expect("Cannot convert to array"),
-        }))
+impl<KeyType, ValueType> Node<KeyType, ValueType> {
+    pub fn new_sentinel(arena: &Arena, top_level: usize) -> *mut Node<KeyType, ValueType> {
+        Node::allocate(arena, MaybeUninit::uninit(), ptr::null_mut(), top_level)
+    }
+
+    pub fn new(
+        arena: &Arena,
+        key: KeyType,
+        value: *mut Value<ValueType>,
+        top_level: usize,
+    ) -> *mut Node<KeyType, ValueType> {
+        Node::allocate(arena, MaybeUninit::new(key), value, top_level)
     }
 
-    pub fn new(key: KeyType, value: ValueType, height: usize) -> *mut Node<ValueType> {
-        let vec = (0..Node::<ValueType>::TOP_LEVEL + 1)
-            .map(|i| AtomicPtr::new(std::ptr::null_mut()))
-            .collect::<Vec<_>>();
-        Box::into_raw(Box::new(Node {
+    fn allocate(
+        arena: &Arena,
+        key: MaybeUninit<KeyType>,
+        value: *mut Value<ValueType>,
+        top_level: usize,
+    ) -> *mut Node<KeyType, ValueType> {
+        let node = arena.allocate(Node::<KeyType, ValueType>::layout(top_level));
+        unsafe { Node::init(node as *mut Node<KeyType, ValueType>, key, value, top_level) }
+    }
+
+    // Layout of a node and its tower.
+    fn layout(top_level: usize) -> Layout {
+        let tower = Layout::array::<AtomicPtr<Node<KeyType, ValueType>>>(top_level + 1).unwrap();
+        let (layout, _) = Layout::new::<Node<KeyType, ValueType>>()
+            .extend(tower)
+            .unwrap();
+        layout
+    }
+
+    // Writes a node with an empty tower to `node`, which must be allocated for `top_level`.
+    unsafe fn init(
+        node: *mut Node<KeyType, ValueType>,
+        key: MaybeUninit<KeyType>,
+        value: *mut Value<ValueType>,
+        top_level: usize,
+    ) -> *mut Node<KeyType, ValueType> {
+        node.write(Node {
             key,
-            value: Some(value),
-            top_level: height,
-            next: vec.try_into().expect("Cannot convert to array"),
-        }))
+            value: AtomicPtr::new(value),
+            top_level,
+            next: [],
+        });
+        for level in 0..=top_level {
+            Node::tower(node)
+                .add(level)
+                .write(AtomicPtr::new(ptr::null_mut()));
+        }
+        node
+    }
+
+    // Successor of `node` at `level`, which must not be above its top level.
+    pub fn next<'a>(
+        node: *mut Node<KeyType, ValueType>,
+        level: usize,
+    ) -> &'a AtomicPtr<Node<KeyType, ValueType>> {
+        unsafe {
+            debug_assert!(level <= (*node).top_level);
+            &*Node::tower(node).add(level)
+        }
+    }
+
+    fn tower(node: *mut Node<KeyType, ValueType>) -> *mut AtomicPtr<Node<KeyType, ValueType>> {
+        unsafe { addr_of_mut!((*node).next) as *mut AtomicPtr<Node<KeyType, ValueType>> }
+    }
+
+    pub fn is_sentinel(&self) -> bool {
+        self.value.load(Ordering::Relaxed).is_null()
+    }
+
+    pub fn key(&self) -> &KeyType {
+        debug_assert!(!self.is_sentinel());
+        unsafe { self.key.assume_init_ref() }
+    }
+
+    // Value of the node, `None` once it is removed.
+    pub fn current_value(&self) -> Option<&ValueType> {
+        let value = self.value.load(Ordering::SeqCst);
+        if get_marker(value) {
+            None
+        } else {
+            Some(unsafe { &(*value).value })
+        }
+    }
+
+    // Drops the key of a node that was never linked into the list. Its value is not its own.
+    pub fn drop_key(node: *mut Node<KeyType, ValueType>) {
+        unsafe { (*node).key.assume_init_drop() }
+    }
+
+    // Drops the key and every value of `node`. Its memory goes with the arena.
+    pub fn reclaim(node: *mut Node<KeyType, ValueType>) {
+        unsafe {
+            if !(*node).is_sentinel() {
+                (*node).key.assume_init_drop();
+            }
+            let mut value = get_node((*node).value.load(Ordering::Relaxed));
+            while !value.is_null() {
+                let older = (*value).older;
+                ptr::drop_in_place(value);
+                value = older;
+            }
+        }
+    }
+}
+
+impl Node<ArenaBytes, ArenaBytes> {
+    // Allocates a node with `key`, `value` and the value holding it in the block of its tower,
+    // laid out as node | tower | value | key bytes | value bytes. Hands back the node and the
+    // size of the block.
+    pub fn new_inline(
+        arena: &Arena,
+        key: &[u8],
+        value: &[u8],
+        top_level: usize,
+    ) -> (*mut Node<ArenaBytes, ArenaBytes>, usize) {
+        let (layout, value_offset) = Node::<ArenaBytes, ArenaBytes>::layout(top_level)
+            .extend(Layout::new::<Value<ArenaBytes>>())
+            .unwrap();
+        let (layout, bytes_offset) = layout
+            .extend(Layout::array::<u8>(key.len() + value.len()).unwrap())
+            .unwrap();
+        let block = arena.allocate(layout);
+        unsafe {
+            let bytes = block.add(bytes_offset);
+            ptr::copy_nonoverlapping(key.as_ptr(), bytes, key.len());
+            ptr::copy_nonoverlapping(value.as_ptr(), bytes.add(key.len()), value.len());
+            let inline = block.add(value_offset) as *mut Value<ArenaBytes>;
+            inline.write(Value {
+                value: ArenaBytes::from_raw_parts(bytes.add(key.len()), value.len()),
+                older: ptr::null_mut(),
+            });
+            let key = ArenaBytes::from_raw_parts(bytes, key.len());
+            let node = Node::init(block as *mut _, MaybeUninit::new(key), inline, top_level);
+            (node, layout.size())
+        }
     }
 }
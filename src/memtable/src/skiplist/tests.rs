@@ -1,150 +1,126 @@
 ﻿#[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use super::super::HazarPointerRecord;
-
-    use crate::skiplist::SkipList;
-    use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
-    use std::sync::Arc;
+    use super::super::node::TOP_LEVEL;
+    use super::super::HP_COUNT;
+    use crate::skiplist::{SkipList, SkipListMemtable};
+    use crate::{Entry, Lookup, Memtable, SequenceNumber, MAX_SEQUENCE};
+    use std::mem::size_of;
+    use std::ops::Bound;
+    use std::sync::atomic::Ordering;
     use std::thread;
 
     #[test]
     fn test_skiplist() {
         let skiplist = SkipList::new();
-        let hp_record = HazarPointerRecord::allocate_hp_record(
-            Arc::clone(&skiplist.hazard_pointer_head),
-            Arc::clone(&skiplist.max_hazard_point_count),
-            5,
-        );
-        skiplist.add(0, "a", hp_record);
-        skiplist.add(1, "b", hp_record);
-        skiplist.add(2, "c", hp_record);
-        skiplist.add(3, "d", hp_record);
-        skiplist.add(4, "e", hp_record);
-        skiplist.add(5, "f", hp_record);
-        skiplist.add(6, "g", hp_record);
-        skiplist.add(7, "h", hp_record);
-        skiplist.add(8, "i", hp_record);
-        skiplist.add(9, "j", hp_record);
-
-        let result = skiplist.find(0, hp_record);
+        let guard = skiplist.guard();
+        skiplist.add(0, "a", &guard);
+        skiplist.add(1, "b", &guard);
+        skiplist.add(2, "c", &guard);
+        skiplist.add(3, "d", &guard);
+        skiplist.add(4, "e", &guard);
+        skiplist.add(5, "f", &guard);
+        skiplist.add(6, "g", &guard);
+        skiplist.add(7, "h", &guard);
+        skiplist.add(8, "i", &guard);
+        skiplist.add(9, "j", &guard);
+
+        let result = skiplist.find(&0, guard.hp_record);
         assert!(result.success);
-        let result = skiplist.find(1, hp_record);
+        let result = skiplist.find(&1, guard.hp_record);
         assert!(result.success);
-        let result = skiplist.find(2, hp_record);
+        let result = skiplist.find(&2, guard.hp_record);
         assert!(result.success);
-        let result = skiplist.find(3, hp_record);
+        let result = skiplist.find(&3, guard.hp_record);
         assert!(result.success);
-        let result = skiplist.find(4, hp_record);
+        let result = skiplist.find(&4, guard.hp_record);
         assert!(result.success);
-        let result = skiplist.find(5, hp_record);
+        let result = skiplist.find(&5, guard.hp_record);
         assert!(result.success);
-        let result = skiplist.find(6, hp_record);
+        let result = skiplist.find(&6, guard.hp_record);
         assert!(result.success);
-        let result = skiplist.find(7, hp_record);
+        let result = skiplist.find(&7, guard.hp_record);
         assert!(result.success);
-        let result = skiplist.find(8, hp_record);
+        let result = skiplist.find(&8, guard.hp_record);
         assert!(result.success);
-        let result = skiplist.find(9, hp_record);
+        let result = skiplist.find(&9, guard.hp_record);
         assert!(result.success);
     }
 
     #[test]
     fn test_skiplist_remove() {
         let skiplist = SkipList::new();
+        let guard = skiplist.guard();
 
-        let hp_record = HazarPointerRecord::allocate_hp_record(
-            Arc::clone(&skiplist.hazard_pointer_head),
-            Arc::clone(&skiplist.max_hazard_point_count),
-            5,
-        );
+        skiplist.add(0, "a", &guard);
+        skiplist.add(1, "b", &guard);
+        skiplist.add(2, "c", &guard);
+        skiplist.add(3, "d", &guard);
+        skiplist.add(4, "e", &guard);
+        skiplist.add(5, "f", &guard);
+        skiplist.add(6, "g", &guard);
+        skiplist.add(7, "h", &guard);
+        skiplist.add(8, "i", &guard);
+        skiplist.add(9, "j", &guard);
 
-        skiplist.add(0, "a", hp_record);
-        skiplist.add(1, "b", hp_record);
-        skiplist.add(2, "c", hp_record);
-        skiplist.add(3, "d", hp_record);
-        skiplist.add(4, "e", hp_record);
-        skiplist.add(5, "f", hp_record);
-        skiplist.add(6, "g", hp_record);
-        skiplist.add(7, "h", hp_record);
-        skiplist.add(8, "i", hp_record);
-        skiplist.add(9, "j", hp_record);
-
-        let success = skiplist.remove(0, hp_record);
+        let success = skiplist.remove(&0, &guard);
         assert!(success);
-        let result = skiplist.find(0, hp_record);
+        let result = skiplist.find(&0, guard.hp_record);
         assert!(!result.success);
-        let success = skiplist.remove(1, hp_record);
+        let success = skiplist.remove(&1, &guard);
         assert!(success);
-        let result = skiplist.find(1, hp_record);
+        let result = skiplist.find(&1, guard.hp_record);
         assert!(!result.success);
-        let success = skiplist.remove(2, hp_record);
+        let success = skiplist.remove(&2, &guard);
         assert!(success);
-        let result = skiplist.find(2, hp_record);
+        let result = skiplist.find(&2, guard.hp_record);
         assert!(!result.success);
-        let success = skiplist.remove(3, hp_record);
+        let success = skiplist.remove(&3, &guard);
         assert!(success);
-        let result = skiplist.find(3, hp_record);
+        let result = skiplist.find(&3, guard.hp_record);
         assert!(!result.success);
     }
 
     #[test]
     fn test_skiplist_parallel_remove() {
         let skiplist = SkipList::new();
-        let head = skiplist.hazard_pointer_head.clone();
-        let total_hptr_count = skiplist.max_hazard_point_count.clone();
+        let guard = skiplist.guard();
 
-        let hp_record = HazarPointerRecord::allocate_hp_record(
-            Arc::clone(&skiplist.hazard_pointer_head),
-            Arc::clone(&skiplist.max_hazard_point_count),
-            5,
-        );
-
-        skiplist.add(0, "a", hp_record);
-        skiplist.add(1, "b", hp_record);
-        skiplist.add(2, "c", hp_record);
-        skiplist.add(3, "d", hp_record);
-        skiplist.add(4, "e", hp_record);
-        skiplist.add(5, "f", hp_record);
-        skiplist.add(6, "g", hp_record);
-        skiplist.add(7, "h", hp_record);
-        skiplist.add(8, "i", hp_record);
-        skiplist.add(9, "j", hp_record);
+        skiplist.add(0, "a", &guard);
+        skiplist.add(1, "b", &guard);
+        skiplist.add(2, "c", &guard);
+        skiplist.add(3, "d", &guard);
+        skiplist.add(4, "e", &guard);
+        skiplist.add(5, "f", &guard);
+        skiplist.add(6, "g", &guard);
+        skiplist.add(7, "h", &guard);
+        skiplist.add(8, "i", &guard);
+        skiplist.add(9, "j", &guard);
 
         thread::scope(|s| {
-
-            let handle = thread::Builder::new()
+            thread::Builder::new()
                 .name("remove_0".into())
                 .spawn_scoped(s, || {
-                    let hp_record = HazarPointerRecord::allocate_hp_record(
-                        Arc::clone(&head),
-                        Arc::clone(&total_hptr_count),
-                        5,
-                    );
-                    let success = skiplist.remove(0, hp_record);
+                    let guard = skiplist.guard();
+                    let success = skiplist.remove(&0, &guard);
                     assert!(success);
-                });
+                })
+                .unwrap();
 
-            let handle = thread::Builder::new()
+            thread::Builder::new()
                 .name("remove_1".into())
                 .spawn_scoped(s, || {
-                    let hp_record = HazarPointerRecord::allocate_hp_record(
-                        Arc::clone(&head),
-                        Arc::clone(&total_hptr_count),
-                        5,
-                    );
-                    let success = skiplist.remove(1, hp_record);
+                    let guard = skiplist.guard();
+                    let success = skiplist.remove(&1, &guard);
                     assert!(success);
-                });
+                })
+                .unwrap();
 
             thread::Builder::new()
                 .name("remove_2".into())
                 .spawn_scoped(s, || {
-                    let hp_record = HazarPointerRecord::allocate_hp_record(
-                        Arc::clone(&head),
-                        Arc::clone(&total_hptr_count),
-                        5,
-                    );
-                    let success = skiplist.remove(2, hp_record);
+                    let guard = skiplist.guard();
+                    let success = skiplist.remove(&2, &guard);
                     assert!(success);
                 })
                 .unwrap();
@@ -152,15 +128,474 @@ mod tests {
             thread::Builder::new()
                 .name("remove_3".into())
                 .spawn_scoped(s, || {
-                    let hp_record = HazarPointerRecord::allocate_hp_record(
-                        Arc::clone(&head),
-                        Arc::clone(&total_hptr_count),
-                        5,
-                    );
-                    let success = skiplist.remove(3, hp_record);
+                    let guard = skiplist.guard();
+                    let success = skiplist.remove(&3, &guard);
                     assert!(success);
                 })
                 .unwrap();
         });
     }
+
+    #[test]
+    fn test_skiplist_guards_are_reused() {
+        let skiplist = SkipList::new();
+        for i in 0..10 {
+            let guard = skiplist.guard();
+            assert!(skiplist.add(i, i, &guard));
+        }
+        //Each guard gave its record back for the next one to take
+        assert_eq!(
+            skiplist.max_hazard_point_count.load(Ordering::SeqCst),
+            HP_COUNT as u32
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "guard was taken from another skip list")]
+    fn test_skiplist_refuses_guard_of_another_list() {
+        let skiplist = SkipList::new();
+        let other = SkipList::<i32, i32>::new();
+        skiplist.add(0, 0, &other.guard());
+    }
+
+    #[test]
+    fn test_skiplist_string_keys() {
+        let skiplist = SkipList::new();
+        let guard = skiplist.guard();
+        for key in ["pear", "apple", "fig", "banana"] {
+            assert!(skiplist.add(key.to_string(), key.len(), &guard));
+        }
+        assert!(!skiplist.add("fig".to_string(), 0, &guard));
+
+        //Looked up by `&str` without allocating a `String`
+        assert_eq!(skiplist.get("fig", &guard), Some(3));
+        assert_eq!(skiplist.get("grape", &guard), None);
+        assert!(skiplist.remove("apple", &guard));
+        let keys: Vec<String> = skiplist.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["banana", "fig", "pear"]);
+    }
+
+    #[test]
+    fn test_skiplist_custom_comparator() {
+        let skiplist = SkipList::with_comparator(|a: &i32, b: &i32| b.cmp(a));
+        let guard = skiplist.guard();
+        for i in 0..10 {
+            skiplist.add(i, i * 10, &guard);
+        }
+        assert_eq!(skiplist.get(&4, &guard), Some(40));
+
+        //Keys come out largest first, and ranges follow the same order
+        let keys: Vec<i32> = skiplist
+            .range((Bound::Included(7), Bound::Excluded(3)))
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, [7, 6, 5, 4]);
+
+        let by_length = SkipList::with_comparator(|a: &String, b: &String| {
+            a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+        });
+        let guard = by_length.guard();
+        for key in ["ccc", "a", "bb", "b"] {
+            by_length.add(key.to_string(), (), &guard);
+        }
+        let keys: Vec<String> = by_length.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["a", "b", "bb", "ccc"]);
+    }
+
+    fn collect<I: Iterator<Item = (i32, i32)>>(iter: I) -> Vec<i32> {
+        iter.map(|(key, value)| {
+            assert_eq!(value, key * 10);
+            key
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_skiplist_iter_and_range() {
+        let skiplist = SkipList::new();
+        let guard = skiplist.guard();
+        assert_eq!(skiplist.iter().next(), None);
+        for i in [5, 1, 8, 3, 9, 0, 2, 7, 4, 6] {
+            skiplist.add(i, i * 10, &guard);
+        }
+
+        assert_eq!(collect(skiplist.iter()), (0..10).collect::<Vec<_>>());
+        assert_eq!(collect(skiplist.range(3..6)), [3, 4, 5]);
+        assert_eq!(collect(skiplist.range(3..=6)), [3, 4, 5, 6]);
+        assert_eq!(collect(skiplist.range(..2)), [0, 1]);
+        assert_eq!(collect(skiplist.range(8..)), [8, 9]);
+        assert_eq!(
+            collect(skiplist.range((Bound::Excluded(2), Bound::Included(4)))),
+            [3, 4]
+        );
+        assert!(collect(skiplist.range(10..)).is_empty());
+        assert!(collect(skiplist.range((Bound::Included(6), Bound::Excluded(3)))).is_empty());
+
+        skiplist.remove(&4, &guard);
+        assert_eq!(collect(skiplist.range(3..6)), [3, 5]);
+    }
+
+    #[test]
+    fn test_skiplist_range_by_borrowed_key() {
+        let skiplist = SkipList::new();
+        let guard = skiplist.guard();
+        for key in ["a", "b", "c", "d"] {
+            skiplist.add(key.to_string(), (), &guard);
+        }
+        let keys: Vec<String> = skiplist
+            .range::<str, _>((Bound::Included("b"), Bound::Excluded("d")))
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, ["b", "c"]);
+    }
+
+    #[test]
+    fn test_skiplist_seek() {
+        let skiplist = SkipList::new();
+        let guard = skiplist.guard();
+        for i in (0..20).step_by(2) {
+            skiplist.add(i, i * 10, &guard);
+        }
+
+        let mut iter = skiplist.iter();
+        iter.seek(&7);
+        assert_eq!(iter.next(), Some((8, 80)));
+        iter.seek(&8);
+        assert_eq!(iter.next(), Some((8, 80)));
+        //Seeking goes back as well
+        iter.seek(&0);
+        assert_eq!(iter.next(), Some((0, 0)));
+        iter.seek(&19);
+        assert_eq!(iter.next(), None);
+
+        //The end of a range stays put
+        let mut iter = skiplist.range(..10);
+        iter.seek(&5);
+        assert_eq!(collect(iter), [6, 8]);
+    }
+
+    #[test]
+    fn test_skiplist_reverse() {
+        let skiplist = SkipList::new();
+        let guard = skiplist.guard();
+        for i in 0..10 {
+            skiplist.add(i, i * 10, &guard);
+        }
+
+        assert_eq!(
+            collect(skiplist.iter().rev()),
+            (0..10).rev().collect::<Vec<_>>()
+        );
+        assert_eq!(collect(skiplist.range(2..5).rev()), [4, 3, 2]);
+        assert_eq!(collect(skiplist.range(..=1).rev()), [1, 0]);
+
+        //Both ends meet in the middle without handing out a key twice
+        let mut iter = skiplist.range(2..8);
+        assert_eq!(iter.next(), Some((2, 20)));
+        assert_eq!(iter.next_back(), Some((7, 70)));
+        assert_eq!(iter.next_back(), Some((6, 60)));
+        assert_eq!(iter.next(), Some((3, 30)));
+        assert_eq!(iter.next(), Some((4, 40)));
+        assert_eq!(iter.next_back(), Some((5, 50)));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    #[test]
+    fn test_skiplist_insert_or_replace() {
+        let skiplist = SkipList::new();
+        let guard = skiplist.guard();
+        assert_eq!(skiplist.insert_or_replace("a", 1, &guard), None);
+        assert_eq!(skiplist.insert_or_replace("a", 2, &guard), Some(1));
+        assert_eq!(skiplist.get("a", &guard), Some(2));
+        assert_eq!(skiplist.len(), 1);
+
+        skiplist.remove("a", &guard);
+        assert_eq!(skiplist.insert_or_replace("a", 3, &guard), None);
+        assert_eq!(skiplist.get("a", &guard), Some(3));
+    }
+
+    #[test]
+    fn test_skiplist_compare_and_swap() {
+        let skiplist = SkipList::new();
+        let guard = skiplist.guard();
+        assert_eq!(skiplist.compare_and_swap("a", &1, 2, &guard), Err(None));
+        skiplist.add("a", 1, &guard);
+        assert_eq!(skiplist.compare_and_swap("a", &1, 2, &guard), Ok(()));
+        assert_eq!(skiplist.compare_and_swap("a", &1, 3, &guard), Err(Some(2)));
+        assert_eq!(skiplist.get("a", &guard), Some(2));
+    }
+
+    #[test]
+    fn test_skiplist_get_or_insert_with() {
+        let skiplist = SkipList::new();
+        let guard = skiplist.guard();
+        assert_eq!(skiplist.get_or_insert_with("a", || 1, &guard), 1);
+        let made = skiplist.get_or_insert_with("a", || unreachable!(), &guard);
+        assert_eq!(made, 1);
+        assert_eq!(skiplist.len(), 1);
+    }
+
+    #[test]
+    fn test_skiplist_parallel_compare_and_swap() {
+        let skiplist = SkipList::new();
+        //Every thread counts up each key, no increment may get lost
+        thread::scope(|s| {
+            for _ in 0..4 {
+                let skiplist = &skiplist;
+                s.spawn(move || {
+                    let guard = skiplist.guard();
+                    for _ in 0..500 {
+                        for key in 0..8 {
+                            let mut count = skiplist.get_or_insert_with(key, || 0, &guard);
+                            while let Err(current) =
+                                skiplist.compare_and_swap(&key, &count, count + 1, &guard)
+                            {
+                                count = current.unwrap();
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        let guard = skiplist.guard();
+        for key in 0..8 {
+            assert_eq!(skiplist.get(&key, &guard), Some(2000));
+        }
+    }
+
+    #[test]
+    fn test_skiplist_parallel_replace_and_remove() {
+        let skiplist = SkipList::new();
+        thread::scope(|s| {
+            for thread in 0..4u32 {
+                let skiplist = &skiplist;
+                s.spawn(move || {
+                    let guard = skiplist.guard();
+                    for round in 0..2000u32 {
+                        let key = round % 16;
+                        if thread % 2 == 0 {
+                            skiplist.remove(&key, &guard);
+                        } else {
+                            skiplist.insert_or_replace(key, round, &guard);
+                        }
+                    }
+                });
+            }
+        });
+
+        //Whatever is left is there exactly once
+        let keys: Vec<u32> = skiplist.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys.len(), skiplist.len());
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_skiplist_parallel_replace_and_remove_owned_values() {
+        //Values that own heap memory, so a value reclaimed twice does not go unnoticed
+        let skiplist: SkipList<String, String> = SkipList::new();
+        thread::scope(|s| {
+            for thread in 0..8u32 {
+                let skiplist = &skiplist;
+                s.spawn(move || {
+                    let guard = skiplist.guard();
+                    for round in 0..20000u32 {
+                        let key = (round % 2).to_string();
+                        if thread % 2 == 0 {
+                            skiplist.remove(&key, &guard);
+                        } else {
+                            skiplist.insert_or_replace(key, format!("{thread}-{round}"), &guard);
+                        }
+                    }
+                });
+            }
+        });
+
+        for (key, value) in skiplist.iter() {
+            let round: u32 = value.split('-').nth(1).unwrap().parse().unwrap();
+            assert_eq!((round % 2).to_string(), key);
+        }
+    }
+
+    #[test]
+    fn test_memtable_keeps_every_version() {
+        let memtable = SkipListMemtable::new();
+        memtable.put(b"a", b"1", 1);
+        memtable.put(b"a", b"3", 3);
+        memtable.put(b"a", b"2", 2);
+        memtable.delete(b"b", 5);
+        memtable.put(b"b", b"4", 4);
+        //The same write made again
+        memtable.put(b"a", b"3", 3);
+
+        assert_eq!(
+            memtable.lookup(b"a", MAX_SEQUENCE),
+            Lookup::Found(b"3".to_vec())
+        );
+        assert_eq!(memtable.lookup(b"b", MAX_SEQUENCE), Lookup::Deleted);
+        assert_eq!(memtable.lookup(b"c", MAX_SEQUENCE), Lookup::NotFound);
+        assert_eq!(memtable.get(b"a"), Some(b"3".to_vec()));
+        assert_eq!(memtable.get(b"b"), None);
+        assert_eq!(memtable.len(), 5);
+
+        //Older snapshots see the versions of their time
+        assert_eq!(memtable.lookup(b"a", 2), Lookup::Found(b"2".to_vec()));
+        assert_eq!(memtable.lookup(b"a", 1), Lookup::Found(b"1".to_vec()));
+        assert_eq!(memtable.lookup(b"a", 0), Lookup::NotFound);
+        assert_eq!(memtable.lookup(b"b", 4), Lookup::Found(b"4".to_vec()));
+        assert_eq!(memtable.lookup(b"b", 3), Lookup::NotFound);
+        let versions: Vec<SequenceNumber> = memtable.iter().map(|entry| entry.sequence).collect();
+        assert_eq!(versions, [3, 2, 1, 5, 4]);
+    }
+
+    #[test]
+    fn test_memtable_iter_and_range() {
+        let memtable = SkipListMemtable::new();
+        for (seq, key) in [b"d", b"b", b"e", b"a", b"c"].iter().enumerate() {
+            memtable.put(*key, *key, seq as SequenceNumber);
+        }
+        memtable.delete(b"c", 10);
+
+        fn keys(entries: impl Iterator<Item = Entry>) -> Vec<Vec<u8>> {
+            entries.map(|entry| entry.key).collect()
+        }
+        assert_eq!(keys(memtable.iter()), [b"a", b"b", b"c", b"c", b"d", b"e"]);
+        assert_eq!(
+            keys(memtable.range(&b"b"[..]..&b"d"[..])),
+            [b"b", b"c", b"c"]
+        );
+        assert_eq!(
+            keys(memtable.range((Bound::Excluded(&b"b"[..]), Bound::Included(&b"d"[..])))),
+            [b"c", b"c", b"d"]
+        );
+        assert_eq!(keys(memtable.range(&b"bb"[..]..)), [b"c", b"c", b"d", b"e"]);
+        assert_eq!(keys(memtable.range(..=&b"a"[..])), [b"a"]);
+        assert_eq!(
+            keys(memtable.iter().rev()),
+            [b"e", b"d", b"c", b"c", b"b", b"a"]
+        );
+        assert!(keys(memtable.range(&b"f"[..]..)).is_empty());
+
+        let deleted = memtable.range(&b"c"[..]..).next().unwrap();
+        assert_eq!(
+            deleted,
+            Entry {
+                key: b"c".to_vec(),
+                sequence: 10,
+                value: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_memtable_memory_usage() {
+        let memtable = SkipListMemtable::new();
+        assert_eq!(memtable.approximate_memory_usage(), 0);
+
+        //One block for the node, its tower, its value and the key, trailer and value bytes
+        memtable.put(b"key", &[0; 100], 1);
+        let one = memtable.approximate_memory_usage();
+        let (word, bytes) = (size_of::<usize>(), 3 + 9 + 100);
+        let shortest = 8 * word + bytes;
+        assert!(one >= shortest, "{one}");
+        assert!(one <= shortest + TOP_LEVEL * word, "{one}");
+        //Newer versions do not take the place of older ones
+        memtable.put(b"key", &[0; 10], 2);
+        let two = memtable.approximate_memory_usage();
+        assert!(two > one + 13);
+        assert_eq!(
+            memtable.lookup(b"key", MAX_SEQUENCE),
+            Lookup::Found(vec![0; 10])
+        );
+        assert_eq!(memtable.lookup(b"key", 1), Lookup::Found(vec![0; 100]));
+        //Writes made again take up nothing
+        memtable.put(b"key", &[0; 100], 1);
+        assert_eq!(memtable.approximate_memory_usage(), two);
+    }
+
+    #[test]
+    fn test_memtable_parallel_writes() {
+        let memtable = SkipListMemtable::new();
+        //Every thread writes every key, the one with the largest sequence number comes first
+        thread::scope(|s| {
+            for thread in 0..4u64 {
+                let memtable = &memtable;
+                s.spawn(move || {
+                    for i in 0..500u64 {
+                        let key = (i * 7919 % 500).to_be_bytes();
+                        let seq = thread * 1000 + i;
+                        memtable.put(&key, &seq.to_be_bytes(), seq);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(memtable.len(), 2000);
+        let entries: Vec<Entry> = memtable.iter().collect();
+        assert_eq!(entries.len(), 2000);
+        for (i, versions) in entries.chunks(4).enumerate() {
+            assert!(versions
+                .iter()
+                .all(|entry| entry.key == (i as u64).to_be_bytes()));
+            assert!(versions[0].sequence >= 3000);
+            assert!(versions.windows(2).all(|w| w[0].sequence > w[1].sequence));
+            for entry in versions {
+                assert_eq!(entry.value, Some(entry.sequence.to_be_bytes().to_vec()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_skiplist_parallel_add_remove_and_iterate() {
+        let skiplist = SkipList::new();
+        let guard = skiplist.guard();
+        for i in 0..200u8 {
+            skiplist.upsert(i, i, |_| false, &guard);
+        }
+
+        thread::scope(|s| {
+            for thread in 0..4u8 {
+                let skiplist = &skiplist;
+                s.spawn(move || {
+                    let guard = skiplist.guard();
+                    for round in 0..50u8 {
+                        for i in (thread..200).step_by(4) {
+                            if round % 2 == 0 {
+                                skiplist.remove(&i, &guard);
+                            } else {
+                                skiplist.upsert(i, i, |_| false, &guard);
+                            }
+                        }
+                    }
+                });
+            }
+            for _ in 0..2 {
+                let skiplist = &skiplist;
+                s.spawn(move || {
+                    for _ in 0..50 {
+                        let keys: Vec<u8> = skiplist
+                            .iter()
+                            .map(|(key, value)| {
+                                assert_eq!(key, value);
+                                key
+                            })
+                            .collect();
+                        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+                        let keys: Vec<u8> =
+                            skiplist.range(50..150).rev().map(|(key, _)| key).collect();
+                        assert!(keys.windows(2).all(|pair| pair[0] > pair[1]));
+                        assert!(keys.iter().all(|key| (50..150).contains(key)));
+                    }
+                });
+            }
+        });
+
+        //Every thread ends on a round that adds its keys back
+        assert_eq!(skiplist.len(), 200);
+        for i in 0..200u8 {
+            assert_eq!(skiplist.get(&i, &guard), Some(i));
+        }
+    }
 }